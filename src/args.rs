@@ -3,6 +3,8 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
+use crate::builder::{BackendKind, Network, PullPolicy};
+
 #[derive(Parser)]
 pub struct Args {
     #[clap(flatten)]
@@ -34,6 +36,17 @@ impl Args {
 #[derive(Subcommand)]
 pub enum Command {
     Build(BuildArgs),
+    /// Remove cached images
+    Prune(PruneArgs),
+    /// Open a shell in the result of a target
+    Shell(ShellArgs),
+    /// Pin the images of FROM commands to their current digests in burt.lock
+    Lock(LockArgs),
+    /// Remove containers left behind by interrupted builds
+    Clean(CleanArgs),
+    /// Export or import cached images
+    #[command(subcommand)]
+    Cache(CacheCommand),
     #[clap(hide(true))]
     InternalContainerCopy {
         src: PathBuf,
@@ -42,6 +55,9 @@ pub enum Command {
     #[clap(hide(true))]
     InternalExport {
         path: PathBuf,
+        /// Write an empty archive if the path does not exist.
+        #[clap(long)]
+        if_exists: bool,
     },
     #[clap(hide(true))]
     InternalImportTar {
@@ -71,9 +87,112 @@ pub struct GlobalArgs {
     /// source file
     #[clap(short, long, default_value="build.burt", global=true)]
     pub file: PathBuf,
+
+    /// container engine to build with
+    #[clap(long, value_enum, default_value="buildah", global=true)]
+    pub backend: BackendKind,
+
+    /// network of RUN commands without a --network option
+    #[clap(long, value_enum, default_value="default", global=true)]
+    pub network_default: Network,
+
+    /// when to pull the images of FROM commands from their registry
+    #[clap(long, value_enum, default_value="missing", global=true)]
+    pub pull: PullPolicy,
+
+    /// only use images in local storage, as with --pull=never
+    #[clap(long, conflicts_with="pull", global=true)]
+    pub offline: bool,
+
+    /// secret for RUN --secret, from a file or environment variable
+    #[clap(long, value_name="id=ID,src=PATH|env=VAR", global=true)]
+    pub secret: Vec<crate::builder::Secret>,
+
+    /// accept local paths outside of the directory of the burt file, relative or absolute
+    #[clap(long, global=true)]
+    pub allow_outside_context: bool,
+
+    /// fail if burt.lock is missing or does not match the burt file
+    #[clap(long, global=true)]
+    pub locked: bool,
+
+    /// open a shell in the container of a failed step
+    #[clap(long, global=true)]
+    pub interactive_on_failure: bool,
+
+    /// ignore cached results, for all targets or only for +TARGET
+    #[clap(long, value_name="+TARGET", num_args=0..=1, require_equals=true, default_missing_value="", global=true)]
+    pub no_cache: Option<Vec<String>>,
 }
 
 #[derive(Parser)]
 pub struct BuildArgs {
     pub targets: Vec<String>, 
 }
+
+#[derive(Parser)]
+pub struct ShellArgs {
+    pub target: String,
+}
+
+#[derive(Parser)]
+pub struct LockArgs {
+    /// targets to lock the images of, defaults to every target in the file
+    pub targets: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct PruneArgs {
+    /// remove images older than this (e.g. 12h, 7d)
+    #[clap(long)]
+    pub older_than: Option<String>,
+
+    /// remove the oldest images until the rest fit in this size (e.g. 10G)
+    #[clap(long)]
+    pub max_size: Option<String>,
+
+    /// remove images not reachable from the targets of the burt file
+    #[clap(long)]
+    pub unreachable: bool,
+
+    /// remove every cached image
+    #[clap(long, conflicts_with_all=["older_than", "max_size", "unreachable"])]
+    pub all: bool,
+
+    /// only list the images that would be removed
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// targets considered reachable, defaults to every target in the file
+    pub targets: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct CleanArgs {
+    /// also remove containers of builds that are still running
+    #[clap(long)]
+    pub all: bool,
+
+    /// only list the containers that would be removed
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Write cached images to an archive
+    Export {
+        /// archive to write
+        #[clap(id="archive", value_name="FILE")]
+        file: PathBuf,
+
+        /// only export images reachable from these targets
+        targets: Vec<String>,
+    },
+    /// Load cached images from an archive
+    Import {
+        /// archive to read
+        #[clap(id="archive", value_name="FILE")]
+        file: PathBuf,
+    },
+}
@@ -1,6 +1,8 @@
 
 use std::path::Path;
+use std::rc::Rc;
 
+use super::backend::{Backend, ContainerOptions};
 use super::container::{Container, ExportDestination};
 
 #[derive(Default)]
@@ -10,13 +12,13 @@ pub struct ArtifactStore {
 
 impl ArtifactStore {
     pub fn save(&mut self, container: &Container, src: &str, dest: &str) -> anyhow::Result<()> {
-        let dest_container = self.ensure_container()?;
+        let dest_container = self.ensure_container(container.backend())?;
         container.copy_to_container(src, dest_container, dest)
     }
 
-    fn ensure_container(&mut self) -> anyhow::Result<&Container> {
+    fn ensure_container(&mut self, backend: &Rc<dyn Backend>) -> anyhow::Result<&Container> {
         if self.container.is_none() {
-            self.container = Some(Container::create("scratch")?);
+            self.container = Some(Container::create(backend, "scratch", &ContainerOptions::default())?);
         }
         Ok(self.container.as_ref().unwrap())
     }
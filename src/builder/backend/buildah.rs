@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::{container_name, owner_label, Backend, CachedImage, ContainerOptions, Network, PullPolicy, WorkingContainer, OWNER_LABEL};
+use crate::builder::command::{CommandError, CommandExt};
+
+/// `buildah from` has no `--cpus`, so it is given as a quota of this period in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+
+pub struct Buildah;
+
+impl Backend for Buildah {
+    fn create(&self, from: &str, options: &ContainerOptions) -> anyhow::Result<String> {
+        let mut command = Command::new("buildah");
+        command.arg("from").arg("--name").arg(container_name());
+        if let Some(memory) = options.memory {
+            command.arg("--memory").arg(memory.to_string());
+        }
+        if let Some(cpus) = options.cpus {
+            command.arg("--cpu-period").arg(CPU_PERIOD.to_string())
+                .arg("--cpu-quota").arg(((cpus * CPU_PERIOD as f64) as u64).to_string());
+        }
+        if options.network != Network::Default {
+            command.arg("--network").arg(options.network.as_str());
+        }
+        for mount in &options.mounts {
+            let mode = if mount.read_only { "ro" } else { "rw" };
+            command.arg("--volume").arg(format!("{}:{}:{}", mount.source.display(), mount.target.display(), mode));
+        }
+        let out = command.arg(from).checked_output()?;
+        let container = String::from_utf8(out.trim_ascii_end().to_vec())?;
+
+        // `buildah from` can't label the container, so it's labelled right after.
+        let labelled = Command::new("buildah")
+            .arg("config")
+            .arg("--label").arg(owner_label())
+            .arg(&container)
+            .checked_output();
+        if let Err(e) = labelled {
+            self.remove(&container)?;
+            return Err(e.into());
+        }
+        Ok(container)
+    }
+
+    fn remove(&self, container: &str) -> anyhow::Result<()> {
+        Command::new("buildah")
+            .arg("rm")
+            .arg(container)
+            .checked_output()?;
+        Ok(())
+    }
+
+    fn list_containers(&self) -> anyhow::Result<Vec<WorkingContainer>> {
+        #[derive(serde::Deserialize)]
+        struct Container {
+            id: String,
+            containername: String,
+        }
+
+        let out = Command::new("buildah")
+            .arg("containers")
+            .arg("--json")
+            .checked_output()?;
+        let containers: Option<Vec<Container>> = serde_json::from_slice(&out)?;
+
+        // Containers can't be filtered by label, so each one's is looked up.
+        let mut working = Vec::new();
+        for c in containers.unwrap_or_default() {
+            let out = Command::new("buildah")
+                .arg("inspect")
+                .arg("--type").arg("container")
+                .arg("--format").arg(format!("{{{{index .OCIv1.Config.Labels \"{OWNER_LABEL}\"}}}}"))
+                .arg(&c.id)
+                .checked_output()?;
+            if let Ok(owner) = std::str::from_utf8(&out)?.trim().parse() {
+                working.push(WorkingContainer { id: c.id, name: c.containername, owner });
+            }
+        }
+        Ok(working)
+    }
+
+    fn commit(&self, container: &str, key: &str, parent: &str) -> anyhow::Result<String> {
+        Command::new("buildah")
+            .arg("config")
+            .arg("--created-by").arg(key)
+            .arg("--label").arg(format!("burt.key={key}"))
+            .arg("--label").arg(format!("burt.parent={parent}"))
+            .arg("--label").arg(format!("{OWNER_LABEL}-"))
+            .arg(container)
+            .checked_output()?;
+
+        let out = Command::new("buildah")
+            .arg("commit")
+            .arg("--quiet")
+            .arg(container)
+            .checked_output()?;
+        Ok(String::from_utf8(out.trim_ascii_end().to_vec())?)
+    }
+
+    fn run(&self, container: &str, env: &[(String, String)]) -> anyhow::Result<Command> {
+        let mut command = Command::new("buildah");
+        command.arg("run");
+        for (name, value) in env {
+            command.arg("--env").arg(format!("{name}={value}"));
+        }
+        command.arg("--").arg(container);
+        Ok(command)
+    }
+
+    fn interactive(&self, container: &str, env: &[(String, String)]) -> anyhow::Result<Command> {
+        let mut command = Command::new("buildah");
+        command.arg("run").arg("--tty");
+        for (name, value) in env {
+            command.arg("--env").arg(format!("{name}={value}"));
+        }
+        command.arg("--").arg(container);
+        Ok(command)
+    }
+
+    fn set_work_dir(&self, container: &str, path: &Path) -> anyhow::Result<()> {
+        Command::new("buildah")
+            .arg("config")
+            .arg("--workingdir")
+            .arg(path)
+            .arg(container).checked_output()?;
+        Ok(())
+    }
+
+    fn work_dir(&self, container: &str) -> anyhow::Result<PathBuf> {
+        let out = Command::new("buildah")
+            .arg("inspect")
+            .arg("--type").arg("container")
+            .arg("--format").arg("{{.OCIv1.Config.WorkingDir}}")
+            .arg(container)
+            .checked_output()?;
+        let path = String::from_utf8(out.trim_ascii_end().to_vec())?;
+        Ok(PathBuf::from(if path.is_empty() { "/" } else { &path }))
+    }
+
+    fn helper(&self, mounts: &[(&str, &str)]) -> Command {
+        let mut command = Command::new("buildah");
+        command.arg("unshare");
+        for (name, container) in mounts {
+            command.arg("-m").arg(format!("{name}={container}"));
+        }
+        command.arg("--").arg(crate::current_exe());
+        command
+    }
+
+    fn find_image(&self, name: &str, platform: Option<&str>) -> anyhow::Result<Option<String>> {
+        let mut command = Command::new("buildah");
+        command.arg("inspect")
+            .arg("--type").arg("image")
+            .arg("--format").arg("{{.FromImageID}} {{.OCIv1.OS}}/{{.OCIv1.Architecture}}")
+            .arg(name);
+        let out = command.output().map_err(|e| CommandError::spawn(&command, e))?;
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            // Buildah has no other way to tell a missing image apart from a failure.
+            if stderr.contains("image not known") {
+                return Ok(None);
+            }
+            anyhow::bail!("`buildah inspect {}` failed with {}:\n{}", name, out.status, stderr.trim_end());
+        }
+
+        let out = String::from_utf8(out.stdout)?;
+        let (id, os_arch) = out.trim_end().split_once(' ').unwrap_or((out.trim_end(), ""));
+        if platform.is_some_and(|p| !super::matches_platform(p, os_arch)) {
+            return Ok(None);
+        }
+        Ok(Some(id.to_owned()))
+    }
+
+    fn fetch_image(&self, name: &str, platform: Option<&str>, pull: PullPolicy) -> anyhow::Result<String> {
+        let mut command = Command::new("buildah");
+        command.arg("pull").arg("--quiet").arg("--policy").arg(pull.as_str());
+        if let Some(platform) = platform {
+            command.arg("--platform").arg(platform);
+        }
+        let out = command.arg(name).checked_output()?;
+
+        Ok(String::from_utf8(out.trim_ascii_end().to_vec())?)
+    }
+
+    fn image_digests(&self, id: &str) -> anyhow::Result<Vec<String>> {
+        #[derive(serde::Deserialize)]
+        struct Image {
+            digest: String,
+            #[serde(default)]
+            names: Vec<String>,
+        }
+
+        let out = Command::new("buildah")
+            .arg("images")
+            .arg("--json")
+            .arg("--digests")
+            .arg(id)
+            .checked_output()?;
+        let images: Option<Vec<Image>> = serde_json::from_slice(&out)?;
+        Ok(images.unwrap_or_default().into_iter()
+            .flat_map(|i| {
+                let named = i.names.into_iter().filter_map(|n| n.split_once('@').map(|(_, d)| d.to_owned()));
+                std::iter::once(i.digest).chain(named).collect::<Vec<_>>()
+            })
+            .collect())
+    }
+
+    fn get_cached_image(&self, key: &str) -> anyhow::Result<Option<String>> {
+        #[derive(serde::Deserialize)]
+        struct Image {
+            id: String,
+            created: u64,
+        }
+
+        let out = Command::new("buildah")
+            .arg("images")
+            .arg("--json")
+            .arg("--filter")
+            .arg(format!("label=burt.key={key}"))
+            .checked_output()?;
+        let images: Option<Vec<Image>> = serde_json::from_slice(&out)?;
+        Ok(images.unwrap_or_default().into_iter().max_by_key(|i| i.created).map(|i| i.id))
+    }
+
+    fn list_cached_images(&self) -> anyhow::Result<Vec<CachedImage>> {
+        #[derive(serde::Deserialize)]
+        struct Image {
+            id: String,
+            created: u64,
+            size: String,
+        }
+
+        let out = Command::new("buildah")
+            .arg("images")
+            .arg("--json")
+            .arg("--filter")
+            .arg("label=burt.key")
+            .checked_output()?;
+        let images: Option<Vec<Image>> = serde_json::from_slice(&out)?;
+
+        images.unwrap_or_default().into_iter().map(|i| {
+            let out = Command::new("buildah")
+                .arg("inspect")
+                .arg("--type").arg("image")
+                .arg("--format").arg("{{json .OCIv1.Config.Labels}}")
+                .arg(&i.id)
+                .checked_output()?;
+            let mut labels: HashMap<String, String> = serde_json::from_slice::<Option<_>>(&out)?.unwrap_or_default();
+            Ok(CachedImage {
+                key: labels.remove("burt.key").unwrap_or_default(),
+                parent: labels.remove("burt.parent"),
+                created: std::time::UNIX_EPOCH + std::time::Duration::from_secs(i.created),
+                size: crate::units::parse_decimal_size(&i.size).unwrap_or(0),
+                id: i.id,
+            })
+        }).collect()
+    }
+
+    fn remove_image(&self, id: &str) -> anyhow::Result<()> {
+        Command::new("buildah")
+            .arg("rmi")
+            .arg(id)
+            .checked_output()?;
+        Ok(())
+    }
+
+    fn push_image(&self, id: &str, layout: &Path, reference: &str) -> anyhow::Result<()> {
+        Command::new("buildah")
+            .arg("push")
+            .arg("--quiet")
+            .arg(id)
+            .arg(format!("oci:{}:{}", layout.display(), reference))
+            .checked_output()?;
+        Ok(())
+    }
+
+    fn pull_image(&self, layout: &Path, reference: &str) -> anyhow::Result<String> {
+        let out = Command::new("buildah")
+            .arg("pull")
+            .arg("--quiet")
+            .arg(format!("oci:{}:{}", layout.display(), reference))
+            .checked_output()?;
+        Ok(String::from_utf8(out.trim_ascii_end().to_vec())?)
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+use sha2::Digest;
+
+use super::{container_name, unique_id, Backend, CachedImage, ContainerOptions, Mount, Network, PullPolicy, WorkingContainer, OWNER_LABEL};
+use crate::builder::command::CommandExt;
+
+/// Directories of the host bind mounted into every mock container for `run`.
+const HOST_DIRS: &[&str] = &["usr", "bin", "sbin", "lib", "lib32", "lib64", "dev", "proc"];
+
+/// Set to make every pull fail, as if the registry could not be reached.
+const OFFLINE_VAR: &str = "BURT_MOCK_OFFLINE";
+
+/// Takes the root, the working directory and the number of mounts, followed
+/// by a source, target and mode for each mount and then the command.
+const RUN_SCRIPT: &str = r#"root=$1; workdir=$2; mounts=$3; shift 3
+for d in usr bin sbin lib lib32 lib64 dev proc; do
+    if [ -d "$root/$d" ] && [ ! -L "$root/$d" ] && [ -d "/$d" ]; then
+        mount --rbind "/$d" "$root/$d" || exit 1
+    fi
+done
+while [ "$mounts" -gt 0 ]; do
+    if [ -d "$1" ]; then
+        mkdir -p "$root/$2"
+    else
+        mkdir -p "$(dirname "$root/$2")" && touch "$root/$2"
+    fi && mount --bind "$1" "$root/$2" || exit 1
+    if [ "$3" = ro ]; then
+        mount -o remount,bind,ro "$root/$2" || exit 1
+    fi
+    mounts=$((mounts - 1)); shift 3
+done
+exec chroot "$root" /bin/sh -c 'cd "$0" && exec "$@"' "$workdir" "$@""#;
+
+/// A backend that keeps images and containers as plain directories.
+///
+/// Images are not downloaded: every image name resolves to an empty root
+/// filesystem, made anew on every pull, and commands are run with the host's `/usr`, `/bin` and
+/// libraries mounted into it. It is meant for tests, not for real builds.
+pub struct Mock {
+    root: PathBuf,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Config {
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    work_dir: Option<PathBuf>,
+    /// Nanoseconds since the epoch, to tell apart images committed within a second.
+    #[serde(default)]
+    created: u64,
+    #[serde(default)]
+    network: Network,
+    #[serde(default)]
+    mounts: Vec<Mount>,
+    /// Set for fetched images, made up from the name of the image.
+    #[serde(default)]
+    digest: Option<String>,
+}
+
+impl Mock {
+    pub fn new(root: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(root.join("images"))?;
+        fs::create_dir_all(root.join("containers"))?;
+        Ok(Self {
+            root
+        })
+    }
+
+    fn image_dir(&self, id: &str) -> PathBuf {
+        self.root.join("images").join(id)
+    }
+
+    fn container_dir(&self, id: &str) -> PathBuf {
+        self.root.join("containers").join(id)
+    }
+
+    fn images(&self) -> anyhow::Result<Vec<(String, Config)>> {
+        let mut images = Vec::new();
+        for entry in fs::read_dir(self.root.join("images"))? {
+            let entry = entry?;
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if let Ok(config) = read_config(&entry.path()) {
+                images.push((id, config));
+            }
+        }
+        images.sort_by_key(|(_, c)| std::cmp::Reverse(c.created));
+        Ok(images)
+    }
+
+    fn resolve_image(&self, name: &str) -> anyhow::Result<Option<String>> {
+        if self.image_dir(name).join("config.json").exists() {
+            return Ok(Some(name.to_owned()));
+        }
+        Ok(self.images()?.into_iter().find(|(_, c)| c.names.iter().any(|n| n == name)).map(|(id, _)| id))
+    }
+}
+
+impl Backend for Mock {
+    /// Memory and cpu limits are not enforced, but `Network::None` gets a network namespace of its own.
+    fn create(&self, from: &str, options: &ContainerOptions) -> anyhow::Result<String> {
+        let id = unique_id();
+        let dir = self.container_dir(&id);
+        fs::create_dir_all(&dir)?;
+
+        let mut config = Config {
+            names: vec![container_name()],
+            network: options.network,
+            mounts: options.mounts.clone(),
+            ..Config::default()
+        };
+        config.labels.insert(OWNER_LABEL.to_owned(), std::process::id().to_string());
+        if from == "scratch" {
+            fs::create_dir(dir.join("rootfs"))?;
+        } else {
+            let image = self.resolve_image(from)?.ok_or_else(|| anyhow::anyhow!("No such image {}", from))?;
+            copy_tree(&self.image_dir(&image).join("rootfs"), &dir.join("rootfs"))?;
+            config.work_dir = read_config(&self.image_dir(&image))?.work_dir;
+        }
+        write_config(&dir, &config)?;
+
+        Ok(id)
+    }
+
+    fn remove(&self, container: &str) -> anyhow::Result<()> {
+        fs::remove_dir_all(self.container_dir(container))?;
+        Ok(())
+    }
+
+    fn list_containers(&self) -> anyhow::Result<Vec<WorkingContainer>> {
+        let mut containers = Vec::new();
+        for entry in fs::read_dir(self.root.join("containers"))? {
+            let entry = entry?;
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let config = read_config(&entry.path()).unwrap_or_default();
+            let owner = config.labels.get(OWNER_LABEL).and_then(|owner| owner.parse().ok());
+            if let (Some(name), Some(owner)) = (config.names.into_iter().next(), owner) {
+                containers.push(WorkingContainer { id, name, owner });
+            }
+        }
+        Ok(containers)
+    }
+
+    fn commit(&self, container: &str, key: &str, parent: &str) -> anyhow::Result<String> {
+        let src = self.container_dir(container);
+        let mut config = read_config(&src)?;
+        config.names.clear();
+        config.labels.remove(OWNER_LABEL);
+        config.network = Network::Default;
+        config.mounts.clear();
+        config.labels.insert("burt.key".to_owned(), key.to_owned());
+        config.labels.insert("burt.parent".to_owned(), parent.to_owned());
+        config.created = now();
+
+        let id = unique_id();
+        let dir = self.image_dir(&id);
+        fs::create_dir_all(&dir)?;
+        copy_tree(&src.join("rootfs"), &dir.join("rootfs"))?;
+        write_config(&dir, &config)?;
+
+        Ok(id)
+    }
+
+    fn run(&self, container: &str, env: &[(String, String)]) -> anyhow::Result<Command> {
+        let dir = self.container_dir(container);
+        let config = read_config(&dir)?;
+
+        let mut command = Command::new("unshare");
+        // Like in a real container, nothing of the environment of burt is seen.
+        command.env_clear()
+            .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
+            .envs(env.iter().map(|(name, value)| (name, value)));
+        // Root needs no user namespace to mount, and one would hide who owns the files.
+        if unsafe { libc::geteuid() } != 0 {
+            command.arg("--user").arg("--map-root-user");
+        }
+        command.arg("--mount");
+        if config.network == Network::None {
+            command.arg("--net");
+        }
+        command.arg("sh").arg("-c").arg(RUN_SCRIPT).arg("sh")
+            .arg(dir.join("rootfs"))
+            .arg(config.work_dir.as_deref().unwrap_or(Path::new("/")))
+            .arg(config.mounts.len().to_string());
+        for mount in &config.mounts {
+            command.arg(&mount.source).arg(&mount.target).arg(if mount.read_only { "ro" } else { "rw" });
+        }
+        Ok(command)
+    }
+
+    fn set_work_dir(&self, container: &str, path: &Path) -> anyhow::Result<()> {
+        let dir = self.container_dir(container);
+        let mut config = read_config(&dir)?;
+        config.work_dir = Some(path.to_owned());
+        write_config(&dir, &config)
+    }
+
+    fn work_dir(&self, container: &str) -> anyhow::Result<PathBuf> {
+        let config = read_config(&self.container_dir(container))?;
+        Ok(config.work_dir.unwrap_or_else(|| PathBuf::from("/")))
+    }
+
+    fn helper(&self, mounts: &[(&str, &str)]) -> Command {
+        let mut command = Command::new(crate::current_exe());
+        for (name, container) in mounts {
+            command.env(name, self.container_dir(container).join("rootfs"));
+        }
+        command
+    }
+
+    /// Every platform gets the same image, as nothing is emulated, and pulling again never changes it.
+    fn find_image(&self, name: &str, _platform: Option<&str>) -> anyhow::Result<Option<String>> {
+        self.resolve_image(name)
+    }
+
+    fn fetch_image(&self, name: &str, _platform: Option<&str>, pull: PullPolicy) -> anyhow::Result<String> {
+        if let Some(id) = self.resolve_image(name)?
+            && pull != PullPolicy::Always
+        {
+            return Ok(id);
+        }
+        if pull == PullPolicy::Never {
+            anyhow::bail!("{} is not in local storage", name);
+        }
+        if std::env::var_os(OFFLINE_VAR).is_some() {
+            anyhow::bail!("Can't pull {}, the registry is not reachable", name);
+        }
+
+        let id = unique_id();
+        let dir = self.image_dir(&id);
+        let rootfs = dir.join("rootfs");
+        fs::create_dir_all(&rootfs)?;
+        for name in HOST_DIRS {
+            let host = Path::new("/").join(name);
+            if let Ok(target) = fs::read_link(&host) {
+                std::os::unix::fs::symlink(target, rootfs.join(name))?;
+            } else if host.is_dir() {
+                fs::create_dir(rootfs.join(name))?;
+            }
+        }
+        fs::create_dir(rootfs.join("tmp"))?;
+
+        let repository = name.split_once('@').map_or(name, |(r, _)| r);
+        let digest = sha2::Sha256::digest(repository.as_bytes());
+        write_config(&dir, &Config {
+            names: vec![name.to_owned()],
+            created: now(),
+            digest: Some(format!("sha256:{}", digest.iter().map(|b| format!("{b:02x}")).collect::<String>())),
+            ..Config::default()
+        })?;
+
+        Ok(id)
+    }
+
+    fn image_digests(&self, id: &str) -> anyhow::Result<Vec<String>> {
+        Ok(read_config(&self.image_dir(id))?.digest.into_iter().collect())
+    }
+
+    fn get_cached_image(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.images()?.into_iter()
+            .find(|(_, c)| c.labels.get("burt.key").is_some_and(|k| k == key))
+            .map(|(id, _)| id))
+    }
+
+    fn list_cached_images(&self) -> anyhow::Result<Vec<CachedImage>> {
+        Ok(self.images()?.into_iter().filter_map(|(id, mut config)| {
+            let key = config.labels.remove("burt.key")?;
+            Some(CachedImage {
+                size: tree_size(&self.image_dir(&id)),
+                created: std::time::UNIX_EPOCH + std::time::Duration::from_nanos(config.created),
+                parent: config.labels.remove("burt.parent"),
+                key,
+                id,
+            })
+        }).collect())
+    }
+
+    fn remove_image(&self, id: &str) -> anyhow::Result<()> {
+        fs::remove_dir_all(self.image_dir(id))?;
+        Ok(())
+    }
+
+    fn push_image(&self, id: &str, layout: &Path, reference: &str) -> anyhow::Result<()> {
+        fs::create_dir_all(layout)?;
+        let file = fs::File::create(layout.join(format!("{reference}.tar")))?;
+        let mut tarb = tar::Builder::new(file);
+        tarb.follow_symlinks(false);
+        tarb.append_dir_all("", self.image_dir(id))?;
+        tarb.finish()?;
+        Ok(())
+    }
+
+    fn pull_image(&self, layout: &Path, reference: &str) -> anyhow::Result<String> {
+        let file = fs::File::open(layout.join(format!("{reference}.tar")))?;
+        let id = unique_id();
+        let mut archive = tar::Archive::new(file);
+        archive.set_preserve_permissions(true);
+        archive.unpack(self.image_dir(&id))?;
+        Ok(id)
+    }
+}
+
+fn read_config(dir: &Path) -> anyhow::Result<Config> {
+    let file = fs::File::open(dir.join("config.json"))?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+fn write_config(dir: &Path, config: &Config) -> anyhow::Result<()> {
+    let file = fs::File::create(dir.join("config.json"))?;
+    serde_json::to_writer(file, config)?;
+    Ok(())
+}
+
+fn copy_tree(src: &Path, dest: &Path) -> anyhow::Result<()> {
+    Command::new("cp").arg("-a").arg(src).arg(dest).checked_output()?;
+    Ok(())
+}
+
+fn tree_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if meta.is_dir() {
+        fs::read_dir(path).into_iter().flatten().flatten().map(|e| tree_size(&e.path())).sum()
+    } else {
+        meta.len()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
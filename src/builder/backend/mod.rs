@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+
+use sha2::Digest;
+
+mod buildah;
+mod mock;
+mod podman;
+
+pub use buildah::Buildah;
+pub use mock::Mock;
+pub use podman::Podman;
+
+/// The container engine used to create, run and commit containers.
+///
+/// Containers and images are referred to by the id the backend returned for them.
+pub trait Backend {
+    /// Create a working container from an image name or id.
+    fn create(&self, from: &str, options: &ContainerOptions) -> anyhow::Result<String>;
+
+    fn remove(&self, container: &str) -> anyhow::Result<()>;
+
+    /// Every working container created by burt, see [`container_name`].
+    fn list_containers(&self) -> anyhow::Result<Vec<WorkingContainer>>;
+
+    /// Commit the container to a new image labelled with `key` and the key of the image it was
+    /// created from, `parent`, returning the image id.
+    fn commit(&self, container: &str, key: &str, parent: &str) -> anyhow::Result<String>;
+
+    /// Command that runs the arguments appended to it inside the container,
+    /// with `env` added to the environment of the image.
+    fn run(&self, container: &str, env: &[(String, String)]) -> anyhow::Result<Command>;
+
+    /// Like [`Backend::run`], but with the command attached to the terminal.
+    fn interactive(&self, container: &str, env: &[(String, String)]) -> anyhow::Result<Command> {
+        self.run(container, env)
+    }
+
+    fn set_work_dir(&self, container: &str, path: &Path) -> anyhow::Result<()>;
+
+    fn work_dir(&self, container: &str) -> anyhow::Result<PathBuf>;
+
+    /// Command that runs burt with the arguments appended to it, with each
+    /// `(variable, container)` pair's root filesystem path in that variable.
+    fn helper(&self, mounts: &[(&str, &str)]) -> Command;
+
+    /// The id of the named image if it is available locally, for `platform` if given, without
+    /// pulling it.
+    fn find_image(&self, name: &str, platform: Option<&str>) -> anyhow::Result<Option<String>>;
+
+    /// Make the named image available locally, for `platform` if given, returning its id.
+    fn fetch_image(&self, name: &str, platform: Option<&str>, pull: PullPolicy) -> anyhow::Result<String>;
+
+    /// Digests of the manifests an image was pulled by.
+    fn image_digests(&self, id: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Find the newest image committed with `key`, as a step built again replaces the image before.
+    fn get_cached_image(&self, key: &str) -> anyhow::Result<Option<String>>;
+
+    /// Every image committed with a key.
+    fn list_cached_images(&self) -> anyhow::Result<Vec<CachedImage>>;
+
+    fn remove_image(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Store an image as `reference` in the OCI layout at `layout`.
+    fn push_image(&self, id: &str, layout: &Path, reference: &str) -> anyhow::Result<()>;
+
+    /// Load `reference` from the OCI layout at `layout`, returning the local image id.
+    fn pull_image(&self, layout: &Path, reference: &str) -> anyhow::Result<String>;
+}
+
+/// How the commands run in a container are confined, fixed when it is created.
+#[derive(Clone, Debug, Default)]
+pub struct ContainerOptions {
+    /// Memory in bytes.
+    pub memory: Option<u64>,
+    pub cpus: Option<f64>,
+    pub network: Network,
+    pub mounts: Vec<Mount>,
+    /// Temporary directories the mounts come from, kept for as long as the container is.
+    pub sources: Vec<Rc<tempfile::TempDir>>,
+}
+
+/// A host directory bind mounted into a container while commands run in it.
+///
+/// Its contents are not part of the committed image.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Mount {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub read_only: bool,
+}
+
+/// Network access of the commands run in a container.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    /// Whatever the backend gives containers by default.
+    #[default]
+    Default,
+    /// Only a loopback interface.
+    None,
+    /// The network of the host.
+    Host,
+}
+
+impl Network {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Network::Default => "default",
+            Network::None => "none",
+            Network::Host => "host",
+        }
+    }
+}
+
+impl std::str::FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Network::Default),
+            "none" => Ok(Network::None),
+            "host" => Ok(Network::Host),
+            _ => anyhow::bail!("Unknown network mode {}, expected none, host or default", s),
+        }
+    }
+}
+
+/// When the images of FROM commands are pulled from their registry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PullPolicy {
+    /// Every time, to pick up a tag that has moved.
+    Always,
+    /// Only if the image is not in local storage.
+    #[default]
+    Missing,
+    /// Never, failing if the image is not in local storage.
+    Never,
+}
+
+impl PullPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PullPolicy::Always => "always",
+            PullPolicy::Missing => "missing",
+            PullPolicy::Never => "never",
+        }
+    }
+}
+
+pub struct CachedImage {
+    pub id: String,
+    pub key: String,
+    /// Key of the image it was built on.
+    pub parent: Option<String>,
+    pub created: std::time::SystemTime,
+    pub size: u64,
+}
+
+pub struct WorkingContainer {
+    pub id: String,
+    pub name: String,
+    /// The process that created the container.
+    pub owner: u32,
+}
+
+/// Whether an image for `os_arch` (like `linux/amd64`) can be used for `platform`, which may also
+/// name a variant.
+fn matches_platform(platform: &str, os_arch: &str) -> bool {
+    platform.strip_prefix(os_arch).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Label of working containers with the id of the process that created them, so containers left
+/// behind by a burt that was killed can be told apart from those of a running one.
+const OWNER_LABEL: &str = "burt.owner";
+
+fn container_name() -> String {
+    format!("burt-{}", unique_id())
+}
+
+/// The owner label for containers created by this process.
+fn owner_label() -> String {
+    format!("{OWNER_LABEL}={}", std::process::id())
+}
+
+fn unique_id() -> String {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut hash = sha2::Sha256::new();
+    hash.update(std::process::id().to_le_bytes());
+    hash.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hash.update(format!("{:?}", std::time::SystemTime::now()).as_bytes());
+    hash.finalize()[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackendKind {
+    Buildah,
+    Podman,
+    Mock,
+}
+
+pub fn new_backend(kind: BackendKind) -> anyhow::Result<Rc<dyn Backend>> {
+    Ok(match kind {
+        BackendKind::Buildah => Rc::new(Buildah),
+        BackendKind::Podman => Rc::new(Podman::default()),
+        BackendKind::Mock => Rc::new(Mock::new(crate::state_dir().join("mock"))?),
+    })
+}
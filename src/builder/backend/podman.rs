@@ -0,0 +1,344 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::{container_name, owner_label, Backend, CachedImage, ContainerOptions, Network, PullPolicy, WorkingContainer, OWNER_LABEL};
+use crate::builder::command::{CommandError, CommandExt};
+
+const SCRATCH_IMAGE: &str = "localhost/burt-scratch";
+
+/// Podman has no separate working containers, so containers are created
+/// running and commands run in them with `podman exec`.
+///
+/// What keeps a container running is the init podman mounts into it with `--init`, started a
+/// second time in pause mode, so nothing is needed from the image. Its entrypoint and command
+/// are put back when the container is committed.
+#[derive(Default)]
+pub struct Podman {
+    work_dirs: RefCell<HashMap<String, PathBuf>>,
+    /// `--change` instructions restoring the entrypoint and command of the image of each container.
+    image_configs: RefCell<HashMap<String, Vec<String>>>,
+}
+
+impl Podman {
+    fn exec(&self, container: &str, env: &[(String, String)], tty: bool) -> anyhow::Result<Command> {
+        Command::new("podman")
+            .arg("start")
+            .arg(container)
+            .checked_output()?;
+
+        let mut command = Command::new("podman");
+        command.arg("exec").arg("--interactive");
+        if tty {
+            command.arg("--tty");
+        }
+        if let Some(path) = self.work_dirs.borrow().get(container) {
+            command.arg("--workdir").arg(path);
+        }
+        for (name, value) in env {
+            command.arg("--env").arg(format!("{name}={value}"));
+        }
+        command.arg(container);
+        Ok(command)
+    }
+
+    fn scratch_image(&self) -> anyhow::Result<&'static str> {
+        if Command::new("podman").arg("image").arg("exists").arg(SCRATCH_IMAGE).checked_output().is_ok() {
+            return Ok(SCRATCH_IMAGE);
+        }
+
+        let mut empty = tempfile::NamedTempFile::new()?;
+        tar::Builder::new(empty.as_file_mut()).finish()?;
+        Command::new("podman")
+            .arg("import")
+            .arg("--quiet")
+            .arg(empty.path())
+            .arg(SCRATCH_IMAGE)
+            .checked_output()?;
+        Ok(SCRATCH_IMAGE)
+    }
+
+    /// `--change` instructions setting the entrypoint and command an image has.
+    fn image_config(&self, image: &str) -> anyhow::Result<Vec<String>> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Config {
+            entrypoint: Option<Vec<String>>,
+            cmd: Option<Vec<String>>,
+        }
+
+        let out = Command::new("podman")
+            .arg("image")
+            .arg("inspect")
+            .arg("--format").arg("{{json .Config}}")
+            .arg(image)
+            .checked_output()?;
+        let config: Option<Config> = serde_json::from_slice(&out)?;
+        let (entrypoint, cmd) = config.map_or((None, None), |c| (c.entrypoint, c.cmd));
+        Ok(vec![
+            format!("ENTRYPOINT {}", serde_json::to_string(&entrypoint.unwrap_or_default())?),
+            format!("CMD {}", serde_json::to_string(&cmd.unwrap_or_default())?),
+        ])
+    }
+}
+
+impl Backend for Podman {
+    fn create(&self, from: &str, options: &ContainerOptions) -> anyhow::Result<String> {
+        let from = if from == "scratch" { self.scratch_image()? } else { from };
+        let image_config = self.image_config(from)?;
+        let mut command = Command::new("podman");
+        command.arg("create")
+            .arg("--name").arg(container_name())
+            .arg("--label").arg(owner_label())
+            .arg("--init")
+            .arg("--entrypoint").arg("/run/podman-init");
+        if let Some(memory) = options.memory {
+            command.arg("--memory").arg(memory.to_string());
+        }
+        if let Some(cpus) = options.cpus {
+            command.arg("--cpus").arg(cpus.to_string());
+        }
+        if options.network != Network::Default {
+            command.arg("--network").arg(options.network.as_str());
+        }
+        for mount in &options.mounts {
+            let mut spec = format!("type=bind,source={},destination={}", mount.source.display(), mount.target.display());
+            if mount.read_only {
+                spec.push_str(",ro");
+            }
+            command.arg("--mount").arg(spec);
+        }
+        let out = command
+            .arg(from)
+            .arg("-P")
+            .checked_output()?;
+        let container = String::from_utf8(out.trim_ascii_end().to_vec())?;
+        self.image_configs.borrow_mut().insert(container.clone(), image_config);
+        Ok(container)
+    }
+
+    fn remove(&self, container: &str) -> anyhow::Result<()> {
+        self.work_dirs.borrow_mut().remove(container);
+        self.image_configs.borrow_mut().remove(container);
+        Command::new("podman")
+            .arg("rm")
+            .arg("--force")
+            .arg(container)
+            .checked_output()?;
+        Ok(())
+    }
+
+    fn list_containers(&self) -> anyhow::Result<Vec<WorkingContainer>> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Container {
+            id: String,
+            names: Vec<String>,
+            #[serde(default)]
+            labels: HashMap<String, String>,
+        }
+
+        let out = Command::new("podman")
+            .arg("ps")
+            .arg("--all")
+            .arg("--filter").arg(format!("label={OWNER_LABEL}"))
+            .arg("--format").arg("json")
+            .checked_output()?;
+        let containers: Option<Vec<Container>> = serde_json::from_slice(&out)?;
+        Ok(containers.unwrap_or_default().into_iter()
+            .filter_map(|c| Some(WorkingContainer {
+                owner: c.labels.get(OWNER_LABEL)?.parse().ok()?,
+                name: c.names.into_iter().next()?,
+                id: c.id,
+            }))
+            .collect())
+    }
+
+    fn commit(&self, container: &str, key: &str, parent: &str) -> anyhow::Result<String> {
+        let mut command = Command::new("podman");
+        command.arg("commit")
+            .arg("--quiet")
+            .arg("--change").arg(format!("LABEL burt.key={key}"))
+            .arg("--change").arg(format!("LABEL burt.parent={parent}"))
+            // Images keep the labels of the container, and there's no removing one.
+            .arg("--change").arg(format!("LABEL {OWNER_LABEL}="));
+        if let Some(path) = self.work_dirs.borrow().get(container) {
+            command.arg("--change").arg(format!("WORKDIR {}", path.display()));
+        }
+        for change in self.image_configs.borrow().get(container).into_iter().flatten() {
+            command.arg("--change").arg(change);
+        }
+        let out = command.arg(container).checked_output()?;
+        Ok(String::from_utf8(out.trim_ascii_end().to_vec())?)
+    }
+
+    fn run(&self, container: &str, env: &[(String, String)]) -> anyhow::Result<Command> {
+        self.exec(container, env, false)
+    }
+
+    fn interactive(&self, container: &str, env: &[(String, String)]) -> anyhow::Result<Command> {
+        self.exec(container, env, true)
+    }
+
+    fn set_work_dir(&self, container: &str, path: &Path) -> anyhow::Result<()> {
+        self.work_dirs.borrow_mut().insert(container.to_owned(), path.to_owned());
+        Ok(())
+    }
+
+    fn work_dir(&self, container: &str) -> anyhow::Result<PathBuf> {
+        if let Some(path) = self.work_dirs.borrow().get(container) {
+            return Ok(path.clone());
+        }
+
+        let out = Command::new("podman")
+            .arg("inspect")
+            .arg("--format").arg("{{.Config.WorkingDir}}")
+            .arg(container)
+            .checked_output()?;
+        let path = String::from_utf8(out.trim_ascii_end().to_vec())?;
+        Ok(PathBuf::from(if path.is_empty() { "/" } else { &path }))
+    }
+
+    fn helper(&self, mounts: &[(&str, &str)]) -> Command {
+        let mut script = String::new();
+        for (idx, (name, _)) in mounts.iter().enumerate() {
+            script.push_str(&format!("{name}=$(podman mount \"${}\") || exit 1; export {name}; ", idx + 1));
+        }
+        script.push_str(&format!("shift {}; exec \"$@\"", mounts.len()));
+
+        let mut command = Command::new("podman");
+        command.arg("unshare")
+            .arg("sh").arg("-c").arg(script).arg("sh")
+            .args(mounts.iter().map(|(_, container)| container))
+            .arg(crate::current_exe());
+        command
+    }
+
+    fn find_image(&self, name: &str, platform: Option<&str>) -> anyhow::Result<Option<String>> {
+        let mut command = Command::new("podman");
+        command.arg("image").arg("exists").arg(name);
+        let status = command.status().map_err(|e| CommandError::spawn(&command, e))?;
+        // 1 is the image missing, anything else a failure.
+        match status.code() {
+            Some(0) => (),
+            Some(1) => return Ok(None),
+            _ => anyhow::bail!("`podman image exists {}` failed with {}", name, status),
+        }
+
+        let out = Command::new("podman")
+            .arg("image")
+            .arg("inspect")
+            .arg("--format").arg("{{.Id}} {{.Os}}/{{.Architecture}}")
+            .arg(name)
+            .checked_output()?;
+        let out = String::from_utf8(out)?;
+        let (id, os_arch) = out.trim_end().split_once(' ').unwrap_or((out.trim_end(), ""));
+        if platform.is_some_and(|p| !super::matches_platform(p, os_arch)) {
+            return Ok(None);
+        }
+        Ok(Some(id.to_owned()))
+    }
+
+    fn fetch_image(&self, name: &str, platform: Option<&str>, pull: PullPolicy) -> anyhow::Result<String> {
+        let mut command = Command::new("podman");
+        command.arg("pull").arg("--quiet").arg("--policy").arg(pull.as_str());
+        if let Some(platform) = platform {
+            command.arg("--platform").arg(platform);
+        }
+        let out = command.arg(name).checked_output()?;
+
+        Ok(String::from_utf8(out.trim_ascii_end().to_vec())?)
+    }
+
+    fn image_digests(&self, id: &str) -> anyhow::Result<Vec<String>> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Image {
+            digest: String,
+            #[serde(default)]
+            repo_digests: Vec<String>,
+        }
+
+        let out = Command::new("podman")
+            .arg("image")
+            .arg("inspect")
+            .arg(id)
+            .checked_output()?;
+        let images: Vec<Image> = serde_json::from_slice(&out)?;
+        Ok(images.into_iter()
+            .flat_map(|i| {
+                let repo = i.repo_digests.into_iter().filter_map(|n| n.split_once('@').map(|(_, d)| d.to_owned()));
+                std::iter::once(i.digest).chain(repo).collect::<Vec<_>>()
+            })
+            .collect())
+    }
+
+    fn get_cached_image(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let out = Command::new("podman")
+            .arg("images")
+            .arg("--quiet")
+            .arg("--sort").arg("created")
+            .arg("--filter")
+            .arg(format!("label=burt.key={key}"))
+            .checked_output()?;
+        let out = String::from_utf8(out)?;
+        Ok(out.lines().next().map(ToOwned::to_owned))
+    }
+
+    fn list_cached_images(&self) -> anyhow::Result<Vec<CachedImage>> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Image {
+            id: String,
+            created: u64,
+            size: u64,
+            #[serde(default)]
+            labels: HashMap<String, String>,
+        }
+
+        let out = Command::new("podman")
+            .arg("images")
+            .arg("--format").arg("json")
+            .arg("--filter").arg("label=burt.key")
+            .checked_output()?;
+        let images: Option<Vec<Image>> = serde_json::from_slice(&out)?;
+
+        Ok(images.unwrap_or_default().into_iter().map(|mut i| {
+            CachedImage {
+                key: i.labels.remove("burt.key").unwrap_or_default(),
+                parent: i.labels.remove("burt.parent"),
+                created: std::time::UNIX_EPOCH + std::time::Duration::from_secs(i.created),
+                size: i.size,
+                id: i.id,
+            }
+        }).collect())
+    }
+
+    fn remove_image(&self, id: &str) -> anyhow::Result<()> {
+        Command::new("podman")
+            .arg("rmi")
+            .arg(id)
+            .checked_output()?;
+        Ok(())
+    }
+
+    fn push_image(&self, id: &str, layout: &Path, reference: &str) -> anyhow::Result<()> {
+        Command::new("podman")
+            .arg("push")
+            .arg("--quiet")
+            .arg(id)
+            .arg(format!("oci:{}:{}", layout.display(), reference))
+            .checked_output()?;
+        Ok(())
+    }
+
+    fn pull_image(&self, layout: &Path, reference: &str) -> anyhow::Result<String> {
+        let out = Command::new("podman")
+            .arg("pull")
+            .arg("--quiet")
+            .arg(format!("oci:{}:{}", layout.display(), reference))
+            .checked_output()?;
+        Ok(String::from_utf8(out.trim_ascii_end().to_vec())?)
+    }
+}
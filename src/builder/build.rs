@@ -1,26 +1,35 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::ffi::OsStr;
+use std::fs;
 use std::io::{self, Read, Seek};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use anyhow::{anyhow, Context};
 use base64::prelude::*;
+use ignore::gitignore::Gitignore;
 use sha2::Digest;
 
 use crate::file::{Command, RootSection, TargetRef};
 
+use super::backend::{Backend, ContainerOptions, Network, PullPolicy};
 use super::container::ExportDestination;
-use super::{artifact, container, hashfile, template};
+use super::lock::{self, ImageRef, LockFile};
+use super::secret::Secret;
+use super::{artifact, container, copy, mount, statcache, template};
 
 macro_rules! ensure_container {
     ($b:expr) => {
         if let Some(c) = $b.container.as_ref() {
             c
         } else if let Some(s) = $b.container_src.as_ref() {
-            $b.container = Some(container::Container::create(&s.from)?);
+            let options = ContainerOptions {
+                network: $b.cache.options.network_default,
+                ..ContainerOptions::default()
+            };
+            $b.container = Some(container::Container::create(&$b.cache.backend, &s.from, &options)?);
             $b.container.as_ref().unwrap()
         } else {
             return Err(anyhow::anyhow!("No container"));
@@ -33,7 +42,14 @@ pub struct Build {
     container_src: Option<ContainerSrc>,
     container: Option<container::Container>,
     artifact_output: artifact::ArtifactStore,
-    environment: template::Environment
+    environment: template::Environment,
+    no_cache: bool,
+    /// Directory of the burt file, which relative host paths are relative to.
+    dir: PathBuf,
+    /// The burt file, canonicalized so that every reference to it compares equal.
+    file: PathBuf,
+    /// Targets being built by this build and the builds it is nested in, outermost first.
+    chain: Vec<(PathBuf, String)>,
 }
 
 impl Build {
@@ -43,10 +59,28 @@ impl Build {
             container_src: None,
             container: None,
             artifact_output: artifact::ArtifactStore::default(),
-            environment: template::Environment::new()
+            environment: template::Environment::new(),
+            no_cache: false,
+            dir: PathBuf::from("."),
+            file: PathBuf::new(),
+            chain: Vec::new(),
         }
     }
 
+    /// A build of another target, from the same burt file unless it builds another.
+    fn sub_build(&self) -> Build {
+        let mut build = Build::new(self.cache.clone());
+        build.dir = self.dir.clone();
+        build.file = self.file.clone();
+        build.chain = self.chain.clone();
+        build
+    }
+
+    /// A path written in the burt file, which relative paths are relative to the directory of.
+    fn local_path(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        Ok(self.dir.join(self.cache.context_path(&self.dir, path)?))
+    }
+
     pub fn set(&mut self, name: &str, value: &str) {
         self.environment.set(name.to_owned(), value);
     }
@@ -55,25 +89,117 @@ impl Build {
         self.artifact_output.export(ExportDestination::Path(path.as_ref()))
     }
 
+    /// Open a shell in the current state of the build.
+    pub fn shell(&mut self) -> anyhow::Result<()> {
+        ensure_container!(self).shell(&[])
+    }
+
     pub fn build(&mut self, path: &Path, target: &str) -> anyhow::Result<()> {
-        let root_config = self.cache.load_burt(path).with_context(|| anyhow!("Failed to load file {}", path.display()))?;
+        let root_config = self.load(path)?;
         self.build_from_config(&root_config, target)
     }
 
     pub fn build_from_config(&mut self, root_config: &Rc<RootSection>, target: &str) -> anyhow::Result<()> {
         let target_def = root_config.targets.get(target).ok_or_else(|| anyhow::anyhow!("No such target"))?;
-        for command in &target_def.commands {
-            self.build_command(root_config, command)?;
-        }
 
+        self.no_cache = self.cache.options.no_cache.applies_to(target);
+        self.enter(target)?;
+        let rv = target_def.commands.iter().try_for_each(|command| self.build_command(root_config, command));
+        self.chain.pop();
+        rv
+    }
+
+    /// Collect the images named by FROM commands of a target and the targets it references,
+    /// without building anything.
+    ///
+    /// Only SET and ARG commands are evaluated, so values read from the build render as empty.
+    pub fn images(&mut self, path: &Path, target: &str, images: &mut BTreeSet<ImageRef>) -> anyhow::Result<()> {
+        let root_config = self.load(path)?;
+        self.images_from_config(&root_config, target, images)
+    }
+
+    fn images_from_config(&mut self, rc: &Rc<RootSection>, target: &str, images: &mut BTreeSet<ImageRef>) -> anyhow::Result<()> {
+        let target_def = rc.targets.get(target).ok_or_else(|| anyhow::anyhow!("No such target"))?;
+
+        self.enter(target)?;
+        let rv = target_def.commands.iter().try_for_each(|command| {
+            let refs = match command {
+                Command::From(f) => match &f.src {
+                    crate::file::FromImage::Image(i) => {
+                        let name = self.environment.render(i)?;
+                        let platform = f.platform.as_deref().map(|p| self.environment.render(p)).transpose()?;
+                        images.insert((name, platform));
+                        vec![]
+                    },
+                    crate::file::FromImage::Target(t) => vec![t],
+                },
+                Command::Copy(c) => c.src.iter().filter_map(|s| match s {
+                    crate::file::CopySource::Artifact(t) => Some(t),
+                    crate::file::CopySource::LocalPath(_) => None,
+                }).collect(),
+                Command::Set(s) => {
+                    self.cmd_set(s)?;
+                    vec![]
+                },
+                _ => vec![],
+            };
+
+            refs.into_iter().try_for_each(|t| {
+                let mut build = self.sub_build();
+                match &t.path {
+                    Some(path) => build.images(&self.local_path(path)?, &t.target, images),
+                    None => build.images_from_config(rc, &t.target, images),
+                }
+            })
+        });
+        self.chain.pop();
+        rv
+    }
+
+    fn load(&mut self, path: &Path) -> anyhow::Result<Rc<RootSection>> {
+        self.dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        let root_config = self.cache.load_burt(path).with_context(|| anyhow!("Failed to load file {}", path.display()))?;
+        self.file = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        Ok(root_config)
+    }
+
+    /// Add a target to the chain, failing if it is already being built.
+    fn enter(&mut self, target: &str) -> anyhow::Result<()> {
+        let link = (self.file.clone(), target.to_owned());
+        if let Some(start) = self.chain.iter().position(|l| *l == link) {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let names: Vec<_> = self.chain[start..].iter().chain([&link])
+                .map(|(file, target)| format!("{}+{}", file.strip_prefix(&cwd).unwrap_or(file).display(), target))
+                .collect();
+            anyhow::bail!("Target references form a cycle: {}", names.join(" -> "));
+        }
+        self.chain.push(link);
         Ok(())
     }
 
-    fn track_changes<F, T, K>(&mut self, key: K, func: F) -> anyhow::Result<T>
+    fn track_changes<F, K>(&mut self, key: K, func: F) -> anyhow::Result<()>
+    where 
+        F: FnMut(&container::Container) -> anyhow::Result<()>,
+        K: ToString
+    {
+        self.track_changes_opts(key, &StepOptions::default(), func)
+    }
+
+    /// Run a step with `func` in a new container and commit the result under a key derived from
+    /// `key` and the previous step.
+    ///
+    /// When an image with that key is cached, `func` is not run at all: the image already holds
+    /// its result, and running it again on top would apply the step twice.
+    fn track_changes_opts<F, K>(&mut self, key: K, opts: &StepOptions, mut func: F) -> anyhow::Result<()>
     where 
-        F: FnOnce(&container::Container) -> anyhow::Result<T>,
+        F: FnMut(&container::Container) -> anyhow::Result<()>,
         K: ToString
     {    
+        super::interrupt::check()?;
+
         let Some(parent) = self.container_src.as_ref() else {
             return Err(anyhow::anyhow!("No container from"));
         };
@@ -83,17 +209,67 @@ impl Build {
         combine_key.update(b"\0");
         combine_key.update(key.to_string().as_bytes());
         let combine_key = format!("burt-{}", BASE64_STANDARD.encode(combine_key.finalize()));
+        self.cache.seen_keys.borrow_mut().insert(combine_key.clone());
+
+        if self.cache.options.keys_only {
+            // Nothing is built, so there is no image for the next step, only its key.
+            self.container = None;
+            self.container_src = Some(ContainerSrc {
+                from: String::new(),
+                key: combine_key,
+                bypass_cache: false,
+            });
+            return Ok(());
+        }
 
-        let src = container::get_cached_image(&combine_key);
-        let src = src.as_deref().unwrap_or(&parent.from);
-        let container = container::Container::create(src)?;
-        let rv = func(&container);
+        // Steps on top of one built without the cache are built again as well, as their cached
+        // images were built on the replaced one.
+        let bypass = opts.no_cache || self.no_cache || parent.bypass_cache;
+        if !bypass && let Some(image) = self.cache.backend.get_cached_image(&combine_key)? {
+            self.container = None;
+            self.container_src = Some(ContainerSrc {
+                from: image,
+                key: combine_key,
+                bypass_cache: false,
+            });
+            return Ok(());
+        }
+
+        let mut container_opts = opts.container.clone();
+        for prepared in &opts.mounts {
+            let (mount, source) = prepared.attach()?;
+            container_opts.mounts.push(mount);
+            container_opts.sources.extend(source);
+        }
+
+        let mut attempt = 0;
+        let (container, rv) = loop {
+            let container = container::Container::create(&self.cache.backend, &parent.from, &container_opts)?;
+            let rv = func(&container);
+            match &rv {
+                Err(e) if attempt < opts.retry && super::interrupt::interrupted().is_none() => {
+                    attempt += 1;
+                    eprintln!("Step failed, retrying ({attempt}/{}): {e:#}", opts.retry);
+                }
+                _ => break (container, rv),
+            }
+        };
+
+        if let Err(e) = &rv && self.cache.options.interactive_on_failure {
+            eprintln!("Step failed: {e:#}");
+            eprintln!("Starting a shell in the failed container, exit it to continue.");
+            // The step's error is what the build fails with, however the shell went.
+            if let Err(shell_error) = container.shell(&opts.env) {
+                eprintln!("{shell_error:#}");
+            }
+        }
 
+        // The container keeps the mounts and limits of the step, so what comes next gets one of its own.
+        self.container = None;
         if rv.is_ok() {
-            self.container_src = Some(container.commit(combine_key)?);
+            self.container_src = Some(container.commit(combine_key, &parent.key, bypass)?);
         }
-        self.container = Some(container);
-        
+
         rv
     }
 
@@ -114,16 +290,16 @@ impl Build {
 
     fn cmd_from(&mut self, rc: &Rc<RootSection>, f: &crate::file::FromCommand) -> anyhow::Result<()> {
         match &f.src {
-            crate::file::FromImage::Image(i) => self.cmd_from_image(i),
+            crate::file::FromImage::Image(i) => self.cmd_from_image(i, f.platform.as_deref()),
             crate::file::FromImage::Target(t) => self.cmd_from_target(rc, t)
         }
     }
 
     fn cmd_from_target(&mut self, rc: &Rc<RootSection>, f: &TargetRef) -> anyhow::Result<()> {
-        let mut build = Build::new(self.cache.clone());
+        let mut build = self.sub_build();
         match &f.path {
             Some(path) => {
-                build.build(path, &f.target)?;
+                build.build(&self.local_path(path)?, &f.target)?;
             },
             None => {
                 build.build_from_config(rc, &f.target)?;
@@ -135,12 +311,40 @@ impl Build {
         Ok(())
     }
 
-    fn cmd_from_image(&mut self, image: &str) -> anyhow::Result<()> {
+    fn cmd_from_image(&mut self, image: &str, platform: Option<&str>) -> anyhow::Result<()> {
         let src = self.environment.render(image)?;
-        self.container_src = Some(ContainerSrc::from(src)?);
+        let platform = platform.map(|p| self.environment.render(p)).transpose()?;
+        let src = self.pinned(src, platform.as_deref())?;
+        let backend = self.cache.backend.as_ref();
+        self.container_src = Some(if self.cache.options.keys_only {
+            // Only an image that is already there is used, as working out keys pulls nothing.
+            ContainerSrc::local(backend, src, platform.as_deref())?.ok_or(KeysUnknown)?
+        } else {
+            ContainerSrc::from(backend, src, platform.as_deref(), self.cache.options.pull)?
+        });
         Ok(())
     }
 
+    /// Stop working out keys at the current step, as the next ones depend on running it.
+    fn keys_unknown(&self) -> anyhow::Error {
+        if let Some(src) = &self.container_src {
+            self.cache.open_keys.borrow_mut().insert(src.key.clone());
+        }
+        KeysUnknown.into()
+    }
+
+    /// The image with the digest the lock file pins it to, which ContainerSrc then verifies.
+    fn pinned(&self, name: String, platform: Option<&str>) -> anyhow::Result<String> {
+        if lock::is_pinned(&name) {
+            return Ok(name);
+        }
+        match self.cache.options.lock.as_ref().and_then(|l| l.digest(&name, platform)) {
+            Some(digest) => Ok(format!("{name}@{digest}")),
+            None if self.cache.options.locked => anyhow::bail!("Image {} is not in burt.lock, run burt lock", name),
+            None => Ok(name),
+        }
+    }
+
     fn cmd_run(&mut self, r: &crate::file::RunCommand) -> anyhow::Result<()> {
         let cmd_args: Vec<Cow<'_, str>> = match &r.cmd {
             crate::file::RunCommandArgs::List(args) => {
@@ -152,19 +356,47 @@ impl Build {
             }
         };
 
-        let key = cmd_args.join("\0");
-        self.track_changes(
-            format!("cmd:{key}"),
-            move |c| {
-                let cmd = c.run()
-                    .args(cmd_args.iter().map(|a| OsStr::new(a.as_ref())));
-        
-                let result = cmd.status()?;
-                if !result.success() {
-                    Err(anyhow::anyhow!("{}", result.code().unwrap_or(-1)))
-                } else {
-                    Ok(())
-                }
+        let network = self.network(r.options.network.as_deref())?;
+        let secrets = r.options.secrets.iter().map(|spec| Ok(format!("type=secret,{}", self.environment.render(spec)?)));
+        let mut mounts = r.options.mounts.iter()
+            .map(|spec| self.environment.render(spec))
+            .chain(secrets)
+            .map(|spec| spec?.parse::<mount::MountSpec>()?.prepare(&self.dir, &self.cache))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut env = Vec::new();
+        if r.options.ssh {
+            mounts.push(mount::ssh_agent());
+            env.push(("SSH_AUTH_SOCK".to_owned(), mount::SSH_AGENT_SOCK.to_owned()));
+        }
+        let render = |value: &Option<String>| value.as_deref().map(|v| self.environment.render(v)).transpose();
+        let timeout = render(&r.options.timeout)?.as_deref().map(crate::units::parse_duration).transpose()?;
+        let opts = StepOptions {
+            no_cache: r.options.no_cache,
+            retry: render(&r.options.retry)?.map(|v| v.parse()).transpose().context("Invalid retry count")?.unwrap_or(0),
+            container: ContainerOptions {
+                memory: render(&r.options.memory)?.as_deref().map(crate::units::parse_size).transpose()?,
+                cpus: render(&r.options.cpus)?.map(|v| v.parse()).transpose().context("Invalid cpu count")?,
+                network,
+                ..ContainerOptions::default()
+            },
+            mounts,
+            env,
+        };
+
+        // Cache mounts, secrets and the SSH agent are left out of the key, as they are not part of the result.
+        let mut key = format!("cmd:network={}", network.as_str());
+        for mount_key in opts.mounts.iter().filter_map(|m| m.key.as_deref()) {
+            key.push_str(&format!(":{mount_key}"));
+        }
+        key.push_str(&format!(":{}", cmd_args.join("\0")));
+        self.track_changes_opts(
+            key,
+            &opts,
+            |c| {
+                c.run(&opts.env)?
+                    .timeout(timeout)
+                    .args(cmd_args.iter().map(|a| OsStr::new(a.as_ref())))
+                    .status()
             }
         )
     }
@@ -177,10 +409,28 @@ impl Build {
     }
 
     fn cmd_save(&mut self, r: &crate::file::SaveArtifactCommand) -> anyhow::Result<()> {
+        // Artifacts only change the keys of COPY commands, which work them out themselves.
+        if self.cache.options.keys_only {
+            return Ok(());
+        }
+
         let container = ensure_container!(self);
         let src = self.environment.render(&r.src)?;
-        let dest = r.dest.as_deref().map(|p| self.environment.render(p)).transpose()?;
-        self.artifact_output.save(container, &src, dest.as_deref().unwrap_or("/"))?;
+        // A relative source is relative to the WORKDIR, as the step it's saved from ran there.
+        let src = if Path::new(&src).is_absolute() {
+            src
+        } else {
+            container.work_dir()?.join(&src).to_string_lossy().into_owned()
+        };
+        // Without a destination, the artifact keeps the name of its source, or the names of what a pattern matched.
+        let dest = match r.dest.as_deref() {
+            Some(dest) => self.environment.render(dest)?,
+            None => match Path::new(&src).file_name() {
+                Some(name) if !copy::is_pattern(&src) => format!("/{}", name.to_string_lossy()),
+                _ => "/".to_owned(),
+            },
+        };
+        self.artifact_output.save(container, &src, &dest)?;
         Ok(())
     }
     
@@ -200,49 +450,149 @@ impl Build {
     }
 
     fn cmd_copy(&mut self, rc: &Rc<RootSection>, c: &crate::file::CopyCommand) -> anyhow::Result<()> {
-        let mut tarfile = tar::Builder::new(hashfile::HashedFile::new(tempfile::tempfile()?));
+        if self.cache.options.keys_only && c.src.iter().any(|s| matches!(s, crate::file::CopySource::Artifact(_))) {
+            // The targets are still worked out for their own keys, but the key of the copy
+            // depends on what their artifacts hold.
+            for inp in &c.src {
+                if let crate::file::CopySource::Artifact(f) = inp {
+                    let mut build = self.sub_build();
+                    let built = match &f.path {
+                        Some(path) => build.build(&self.local_path(path)?, &f.target),
+                        None => build.build_from_config(rc, &f.target),
+                    };
+                    if let Err(e) = built && !e.is::<KeysUnknown>() {
+                        return Err(e);
+                    }
+                }
+            }
+            return Err(self.keys_unknown());
+        }
 
-        let dest = self.environment.render(&c.dest)?;
+        let mut archive = copy::CopyArchive::default();
+        archive.set_ignore(self.cache.load_ignore(&self.dir)?);
+
+        // Local paths, and artifacts unpacked into the archive, with the path they were given as.
+        let mut sources = Vec::new();
         for inp in &c.src {
             match inp {
                 crate::file::CopySource::LocalPath(path) => {
-                    let inp = self.environment.render(path)?;
-                    tarfile.append_path(inp)?;
+                    let given = self.environment.render(path)?;
+                    let relative = self.cache.context_path(&self.dir, Path::new(&given))?;
+                    if copy::is_pattern(&given) {
+                        let matches = copy::glob_paths(&copy::context_pattern(&self.dir, &relative))?;
+                        if matches.is_empty() && !c.options.if_exists {
+                            anyhow::bail!("COPY pattern {} matched nothing", given);
+                        }
+                        for path in matches {
+                            let relative = path.strip_prefix(&self.dir).unwrap_or(&path).to_owned();
+                            sources.push((path, relative, true));
+                        }
+                        continue;
+                    }
+
+                    let path = self.dir.join(&relative);
+                    if c.options.if_exists && fs::symlink_metadata(&path).is_err() {
+                        continue;
+                    }
+                    sources.push((path, relative, true));
                 }
                 crate::file::CopySource::Artifact(f) => {
-                    let mut writer = tarfile.into_inner()?;
-                    
-                    let mut build = Build::new(self.cache.clone());
+                    let mut build = self.sub_build();
                     match &f.path {
                         Some(path) => {
-                            build.build(path, &f.target)?;
+                            build.build(&self.local_path(path)?, &f.target)?;
                         },
                         None => {
                             build.build_from_config(rc, &f.target)?;
                         }
                     }
 
-                    let build_container = ensure_container!(&mut build);
-                    let art_path = f.artifact.as_deref().unwrap_or("/");
-                    build_container.export(Path::new(art_path), ExportDestination::Writer(&mut writer))?;
-                    tarfile = tar::Builder::new(writer);
+                    let mut buffer = tempfile::tempfile()?;
+                    let build_container = ensure_container!(build);
+                    let art_path = Path::new(f.artifact.as_deref().unwrap_or("/"));
+                    // The target itself still has to build, only a missing artifact is skipped.
+                    if c.options.if_exists {
+                        build_container.export_if_exists(art_path, ExportDestination::Writer(&mut buffer))?;
+                    } else {
+                        build_container.export(art_path, ExportDestination::Writer(&mut buffer))?;
+                    }
+                    buffer.seek(io::SeekFrom::Start(0))?;
+                    let unpacked = archive.unpack(buffer)?;
+                    let path = art_path.file_name().map_or(unpacked.clone(), |name| unpacked.join(name));
+                    if c.options.if_exists && fs::symlink_metadata(&path).is_err() {
+                        continue;
+                    }
+                    sources.push((path, art_path.to_owned(), false));
                 }
             }
         }
 
-        tarfile.finish()?;
-        let (mut tarfile, hash) = tarfile.into_inner()?.finish()?;
+        // Like in a Dockerfile, the contents of directories are copied into the
+        // destination, and a file is copied into it only if it ends with a slash.
+        let dest = self.environment.render(&c.dest)?;
+        let into_dir = c.options.parents || copy::names_directory(Path::new(&dest));
+        if sources.len() > 1 && !into_dir {
+            anyhow::bail!("COPY with more than one source needs a destination ending with /, not {}", dest);
+        }
+        let mut root = PathBuf::from(&dest);
+        for (path, given, local) in &sources {
+            let name = if c.options.parents {
+                copy::parents_name(given)
+            } else if path.is_dir() {
+                PathBuf::new()
+            } else if into_dir {
+                PathBuf::from(path.file_name().ok_or_else(|| anyhow!("Invalid source {}", path.display()))?)
+            } else {
+                let dest = Path::new(&dest);
+                root = dest.parent().filter(|p| p != &Path::new("")).unwrap_or(Path::new(".")).to_owned();
+                PathBuf::from(dest.file_name().unwrap())
+            };
+            if *local {
+                archive.add_path(path, &name).with_context(|| format!("Failed to read {}", given.display()))?;
+            } else {
+                archive.add_unpacked(path, &name).with_context(|| format!("Failed to read {}", given.display()))?;
+            }
+        }
+
+        let chown = c.options.chown.as_deref().map(|v| self.environment.render(v)).transpose()?;
+        let chmod = c.options.chmod.as_deref().map(|v| self.environment.render(v)).transpose()?;
+        if let Some(mode) = &chmod {
+            archive.set_mode(copy::parse_mode(mode)?);
+        }
 
-        tarfile.seek(io::SeekFrom::Start(0))?;
+        let key = self.cache.with_stat_cache(|stats| archive.key(stats))?;
+        let mut key = format!("copy:{key}:{dest}");
+        if let Some(chown) = &chown {
+            key.push_str(&format!(":chown={chown}"));
+        }
+        if let Some(chmod) = &chmod {
+            key.push_str(&format!(":chmod={chmod}"));
+        }
+        if c.options.parents {
+            key.push_str(":parents");
+        }
+        if c.options.if_exists {
+            key.push_str(":if-exists");
+        }
         self.track_changes(
-            format!("copy-tar:{}:{}", BASE64_STANDARD.encode(hash), dest),
+            key,
             move |c| {
-                c.import_tar(tarfile, &dest)
+                if let Some(chown) = &chown {
+                    let (uid, gid) = resolve_owner(c, chown)?;
+                    archive.set_owner(uid, gid);
+                }
+                let mut tarfile = archive.write_tar(tempfile::tempfile()?)?;
+                tarfile.seek(io::SeekFrom::Start(0))?;
+                c.import_tar(tarfile, &root)
             }
         )
     }
 
     fn cmd_read_file(&mut self, r: &crate::file::ReadFileCommand) -> anyhow::Result<()> {
+        if self.cache.options.keys_only {
+            return Err(self.keys_unknown());
+        }
+
         let container = ensure_container!(self);
         let src = self.environment.render(&r.src)?;
 
@@ -260,8 +610,32 @@ impl Build {
         Ok(())
     }
 
+    /// The network of a RUN or READ RUN with the given `--network` option.
+    fn network(&self, option: Option<&str>) -> anyhow::Result<Network> {
+        match option {
+            Some(v) => self.environment.render(v)?.parse(),
+            None => Ok(self.cache.options.network_default),
+        }
+    }
+
     fn cmd_read_run(&mut self, r: &crate::file::ReadRunCommand) -> anyhow::Result<()> {
-        let container = ensure_container!(self);
+        if self.cache.options.keys_only {
+            return Err(self.keys_unknown());
+        }
+
+        let network = self.network(r.network.as_deref())?;
+        // The network of a container is fixed, so another one is needed to change it.
+        let temporary = if ensure_container!(self).network() == network {
+            None
+        } else {
+            let src = self.container_src.as_ref().ok_or_else(|| anyhow!("No container"))?;
+            let options = ContainerOptions {
+                network,
+                ..ContainerOptions::default()
+            };
+            Some(container::Container::create(&self.cache.backend, &src.from, &options)?)
+        };
+        let container = temporary.as_ref().or(self.container.as_ref()).unwrap();
 
         let cmd_args: Vec<Cow<'_, str>> = match &r.src {
             crate::file::RunCommandArgs::List(args) => {
@@ -273,9 +647,9 @@ impl Build {
             }
         };
 
-        let cmd = container.run()
+        let cmd = container.run(&[])?
             .args(cmd_args.iter().map(|a| OsStr::new(a.as_ref())));
-        let output = cmd.output()?;
+        let output = String::from_utf8(cmd.output()?).context("Command output is not UTF-8")?;
 
         self.environment.set(r.dest.clone(), output);
 
@@ -283,28 +657,214 @@ impl Build {
     }
 }
 
+/// The user and group ids of a `--chown` value, with names looked up in the container.
+fn resolve_owner(container: &container::Container, spec: &str) -> anyhow::Result<(u64, u64)> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+
+    let (uid, primary_gid) = match user.parse() {
+        Ok(uid) => (uid, Some(uid)),
+        Err(_) => {
+            let passwd = container.read_file(Path::new("/etc/passwd"))
+                .with_context(|| format!("Failed to look up user {user}"))?;
+            copy::lookup_id(&passwd, user).ok_or_else(|| anyhow!("Unknown user {}", user))?
+        }
+    };
+    let gid = match group {
+        None => primary_gid.ok_or_else(|| anyhow!("User {} has no group", user))?,
+        Some(group) => match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => {
+                let groups = container.read_file(Path::new("/etc/group"))
+                    .with_context(|| format!("Failed to look up group {group}"))?;
+                copy::lookup_id(&groups, group).ok_or_else(|| anyhow!("Unknown group {}", group))?.0
+            }
+        },
+    };
+    Ok((uid, gid))
+}
+
 pub struct ContainerSrc {
     pub from: String,
-    pub key: String
+    pub key: String,
+    /// Built without looking up the cache, so the steps built on it can't use it either.
+    pub bypass_cache: bool,
 }
 
 impl ContainerSrc {
-    pub fn from(name: String) -> anyhow::Result<Self> {
-        let key = container::fetch_image(&name)?;
+    pub fn from(backend: &dyn Backend, name: String, platform: Option<&str>, pull: PullPolicy) -> anyhow::Result<Self> {
+        // The empty image, which every backend creates containers from without fetching anything.
+        if name == "scratch" {
+            return Ok(Self {
+                from: name,
+                key: "from-scratch".to_owned(),
+                bypass_cache: false,
+            });
+        }
+
+        let id = fetch_image(backend, &name, platform, pull)?;
+        Self::image(backend, &name, id, platform)
+    }
+
+    /// Like [`ContainerSrc::from`], but only for an image that is available without pulling it.
+    pub fn local(backend: &dyn Backend, name: String, platform: Option<&str>) -> anyhow::Result<Option<Self>> {
+        if name == "scratch" {
+            return Self::from(backend, name, platform, PullPolicy::Never).map(Some);
+        }
+        backend.find_image(&name, platform)?
+            .map(|id| Self::image(backend, &name, id, platform))
+            .transpose()
+    }
+
+    fn image(backend: &dyn Backend, name: &str, id: String, platform: Option<&str>) -> anyhow::Result<Self> {
+        if let Some((_, expected)) = name.split_once('@') {
+            let digests = backend.image_digests(&id)?;
+            if !digests.iter().any(|d| d == expected) {
+                anyhow::bail!("Image {} does not match its digest, it has {}", name, digests.join(", "));
+            }
+        }
+
+        let key = match platform {
+            Some(platform) => format!("from-{id}:platform={platform}"),
+            None => format!("from-{id}"),
+        };
+        // By id, as the name may since have been pulled for another platform.
         Ok(Self {
-            from: name,
-            key: format!("from-{key}")
+            from: id,
+            key,
+            bypass_cache: false,
         })
     }
 }
 
+/// Fetch an image, or with `PullPolicy::Never` only look it up in local storage.
+pub fn fetch_image(backend: &dyn Backend, name: &str, platform: Option<&str>, pull: PullPolicy) -> anyhow::Result<String> {
+    if pull == PullPolicy::Never {
+        return backend.find_image(name, platform)
+            .with_context(|| format!("Failed to look up image {}", name))?
+            .ok_or_else(|| anyhow::anyhow!("Image {} is not available locally and pulling is disabled", name));
+    }
+    backend.fetch_image(name, platform, pull).with_context(|| format!("Failed to fetch image {}", name))
+}
+
+/// Where working out the keys of a target stopped, as the keys of the steps after it depend on
+/// running the steps before.
+#[derive(Debug)]
+pub struct KeysUnknown;
+
+impl std::fmt::Display for KeysUnknown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The keys of the following steps depend on running the ones before")
+    }
+}
+
+impl std::error::Error for KeysUnknown {}
+
+/// How a step is run when it has no cached image.
+#[derive(Default)]
+struct StepOptions {
+    /// Run the step even if it has a cached image.
+    no_cache: bool,
+    /// Times to run the step again in a fresh container after it fails.
+    retry: u32,
+    container: ContainerOptions,
+    /// Mounts added to those of `container`, set up only if the step runs.
+    mounts: Vec<mount::PreparedMount>,
+    /// Environment the step runs its command with, which a shell in its container gets as well.
+    env: Vec<(String, String)>,
+}
 
 #[derive(Default)]
+pub struct BuildOptions {
+    pub no_cache: NoCache,
+    /// Only work out the keys of the steps, without fetching images or running anything.
+    pub keys_only: bool,
+    /// Open a shell in the container of a step that failed before removing it.
+    pub interactive_on_failure: bool,
+    /// Network of RUN and READ RUN commands without a `--network` option.
+    pub network_default: Network,
+    /// Accept relative paths leading out of the directory of the burt file.
+    pub allow_outside_context: bool,
+    /// Values RUN commands can mount with `--secret`.
+    pub secrets: Vec<Secret>,
+    /// When the images of FROM commands are pulled.
+    pub pull: PullPolicy,
+    /// Digests to pull the images of FROM commands by.
+    pub lock: Option<LockFile>,
+    /// Fail on images that have no digest in `lock`.
+    pub locked: bool,
+}
+
+/// Which targets should ignore previously cached images.
+#[derive(Default)]
+pub enum NoCache {
+    #[default]
+    None,
+    All,
+    Targets(HashSet<String>),
+}
+
+impl NoCache {
+    fn applies_to(&self, target: &str) -> bool {
+        match self {
+            NoCache::None => false,
+            NoCache::All => true,
+            NoCache::Targets(targets) => targets.contains(target),
+        }
+    }
+}
+
 pub struct BurtCache {
-    burts: RefCell<HashMap<PathBuf, Rc<RootSection>>>
+    backend: Rc<dyn Backend>,
+    burts: RefCell<HashMap<PathBuf, Rc<RootSection>>>,
+    ignores: RefCell<HashMap<PathBuf, Rc<Gitignore>>>,
+    options: BuildOptions,
+    seen_keys: RefCell<HashSet<String>>,
+    open_keys: RefCell<HashSet<String>>,
+    stat_cache: RefCell<Option<statcache::StatCache>>,
 }
 
 impl BurtCache {
+    pub fn new(backend: Rc<dyn Backend>, options: BuildOptions) -> Self {
+        Self {
+            backend,
+            burts: RefCell::default(),
+            ignores: RefCell::default(),
+            options,
+            seen_keys: RefCell::default(),
+            open_keys: RefCell::default(),
+            stat_cache: RefCell::default(),
+        }
+    }
+
+    pub(super) fn with_stat_cache<F, T>(&self, func: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut statcache::StatCache) -> std::io::Result<T>
+    {
+        let path = crate::state_dir().join("stat-cache.json");
+        let mut borrow = self.stat_cache.borrow_mut();
+        let stats = borrow.get_or_insert_with(|| statcache::StatCache::load(&path));
+        let rv = func(stats)?;
+        stats.save(&path)?;
+        Ok(rv)
+    }
+
+    pub(super) fn secret(&self, id: &str) -> Option<&Secret> {
+        self.options.secrets.iter().rev().find(|s| s.id == id)
+    }
+
+    /// Cache keys of every step evaluated so far.
+    pub fn seen_keys(&self) -> HashSet<String> {
+        self.seen_keys.borrow().clone()
+    }
+
+    /// Keys of the steps where working out keys stopped, see [`KeysUnknown`].
+    pub fn open_keys(&self) -> HashSet<String> {
+        self.open_keys.borrow().clone()
+    }
+
     fn load_burt(&self, path: &Path) -> anyhow::Result<Rc<RootSection>> {
         let mut borrow = self.burts.borrow_mut();
         if let Some(v) = borrow.get(path) {
@@ -315,6 +875,21 @@ impl BurtCache {
         borrow.insert(path.to_owned(), burtfile.clone());
         Ok(burtfile)
     }
+
+    pub(super) fn context_path(&self, dir: &Path, path: &Path) -> anyhow::Result<PathBuf> {
+        copy::context_path(dir, path, self.options.allow_outside_context)
+    }
+
+    fn load_ignore(&self, dir: &Path) -> anyhow::Result<Rc<Gitignore>> {
+        let mut borrow = self.ignores.borrow_mut();
+        if let Some(v) = borrow.get(dir) {
+            return Ok(v.clone());
+        }
+
+        let ignore = Rc::new(copy::load_ignore(dir)?);
+        borrow.insert(dir.to_owned(), ignore.clone());
+        Ok(ignore)
+    }
 }
 
 
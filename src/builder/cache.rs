@@ -0,0 +1,92 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::backend::Backend;
+
+const INDEX_NAME: &str = "burt-cache.json";
+const LAYOUT_NAME: &str = "oci";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CacheIndex {
+    images: Vec<CacheIndexEntry>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CacheIndexEntry {
+    key: String,
+    reference: String,
+}
+
+/// Write cached images to an archive holding an OCI layout and an index of their cache keys.
+///
+/// If `keys` is given, only images with one of those keys are exported.
+pub fn export_cache(backend: &dyn Backend, path: &Path, keys: Option<&HashSet<String>>) -> anyhow::Result<()> {
+    let mut images = backend.list_cached_images()?;
+    images.sort_by_key(|i| std::cmp::Reverse(i.created));
+
+    let mut newest = HashMap::new();
+    for image in images {
+        if keys.is_some_and(|k| !k.contains(&image.key)) {
+            continue;
+        }
+        newest.entry(image.key.clone()).or_insert(image);
+    }
+
+    let tempdir = tempfile::tempdir()?;
+    let layout = tempdir.path().join(LAYOUT_NAME);
+    let mut index = CacheIndex {
+        images: Vec::new()
+    };
+
+    for (idx, (key, image)) in newest.into_iter().enumerate() {
+        let reference = format!("burt-{idx}");
+        backend.push_image(&image.id, &layout, &reference)?;
+        index.images.push(CacheIndexEntry {
+            key,
+            reference,
+        });
+    }
+
+    let index_file = fs::File::create(tempdir.path().join(INDEX_NAME))?;
+    serde_json::to_writer(index_file, &index)?;
+
+    let file = fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut tarb = tar::Builder::new(file);
+    tarb.append_path_with_name(tempdir.path().join(INDEX_NAME), INDEX_NAME)?;
+    if layout.exists() {
+        tarb.append_dir_all(LAYOUT_NAME, &layout)?;
+    }
+    tarb.finish()?;
+
+    println!("Exported {} images to {}", index.images.len(), path.display());
+
+    Ok(())
+}
+
+/// Load the images of an archive written by [`export_cache`].
+pub fn import_cache(backend: &dyn Backend, path: &Path) -> anyhow::Result<()> {
+    let file = fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let tempdir = tempfile::tempdir()?;
+    tar::Archive::new(file).unpack(tempdir.path())?;
+
+    let index_file = fs::File::open(tempdir.path().join(INDEX_NAME))
+        .with_context(|| format!("{} is not a burt cache archive", path.display()))?;
+    let index: CacheIndex = serde_json::from_reader(index_file)?;
+    let layout = tempdir.path().join(LAYOUT_NAME);
+
+    let mut imported = 0;
+    for entry in &index.images {
+        if backend.get_cached_image(&entry.key)?.is_some() {
+            continue;
+        }
+        backend.pull_image(&layout, &entry.reference)?;
+        imported += 1;
+    }
+
+    println!("Imported {} of {} images from {}", imported, index.images.len(), path.display());
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use super::backend::Backend;
+use super::interrupt::is_running;
+
+/// Remove working containers left behind by burt processes that are no
+/// longer running, or by every burt process if `all` is set.
+pub fn clean(backend: &dyn Backend, all: bool, dry_run: bool) -> anyhow::Result<()> {
+    for container in backend.list_containers()? {
+        if !all && is_running(container.owner) {
+            continue;
+        }
+
+        if dry_run {
+            println!("Would remove {}", container.name);
+        } else {
+            println!("Removing {}", container.name);
+            backend.remove(&container.id)?;
+        }
+    }
+
+    Ok(())
+}
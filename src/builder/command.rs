@@ -0,0 +1,272 @@
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt as _;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Only the end of a command's stderr is kept for error messages.
+const STDERR_LIMIT: usize = 16 * 1024;
+
+/// Values that must not be shown, see [`redact`].
+static REDACTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Hide `value` from the command lines and output shown from now on, however short it is.
+pub fn redact(value: &str) {
+    let value = value.trim();
+    if value.is_empty() {
+        return;
+    }
+    let mut redacted = REDACTED.lock().unwrap();
+    if !redacted.iter().any(|v| v == value) {
+        redacted.push(value.to_owned());
+    }
+}
+
+fn redacted(text: &str) -> String {
+    REDACTED.lock().unwrap().iter().fold(text.to_owned(), |text, value| text.replace(value.as_str(), "***"))
+}
+
+/// An external command that could not be started or did not succeed.
+#[derive(Debug)]
+pub struct CommandError {
+    command: String,
+    kind: CommandErrorKind,
+}
+
+#[derive(Debug)]
+enum CommandErrorKind {
+    Spawn(io::Error),
+    Status {
+        status: ExitStatus,
+        stderr: String,
+    },
+    Timeout(Duration),
+}
+
+impl CommandError {
+    pub fn spawn(command: &Command, err: io::Error) -> Self {
+        Self {
+            command: command_line(command),
+            kind: CommandErrorKind::Spawn(err),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            CommandErrorKind::Spawn(e) => write!(f, "Failed to run `{}`: {}", self.command, e),
+            CommandErrorKind::Status { status, stderr } if stderr.is_empty() => {
+                write!(f, "`{}` failed with {}", self.command, status)
+            },
+            CommandErrorKind::Status { status, stderr } => {
+                write!(f, "`{}` failed with {}:\n{}", self.command, status, stderr)
+            },
+            CommandErrorKind::Timeout(timeout) => {
+                write!(f, "`{}` was killed after running for {:?}", self.command, timeout)
+            },
+        }
+    }
+}
+
+pub trait CommandExt {
+    /// Run to completion and return stdout, failing unless the command succeeds.
+    fn checked_output(&mut self) -> Result<Vec<u8>, CommandError>;
+
+    /// Run to completion with its output shown, failing unless the command
+    /// succeeds within `timeout`.
+    ///
+    /// Stderr is also kept for the error. While there are values to [`redact`], stdout is
+    /// passed through burt to hide them rather than inherited.
+    fn checked_status(&mut self, timeout: Option<Duration>) -> Result<(), CommandError>;
+
+    /// Start the command with stderr captured, to be finished by [`CheckedChild::wait`].
+    fn checked_spawn(&mut self) -> Result<CheckedChild, CommandError>;
+}
+
+impl CommandExt for Command {
+    fn checked_output(&mut self) -> Result<Vec<u8>, CommandError> {
+        let mut child = spawn(self.stdout(Stdio::piped()), false)?;
+        let mut stdout = Vec::new();
+        let read = child.child.stdout.take().unwrap().read_to_end(&mut stdout);
+        child.wait()?;
+        read.map_err(|e| CommandError::spawn(self, e))?;
+        Ok(stdout)
+    }
+
+    fn checked_status(&mut self, timeout: Option<Duration>) -> Result<(), CommandError> {
+        spawn(self, true)?.wait_timeout(timeout)
+    }
+
+    fn checked_spawn(&mut self) -> Result<CheckedChild, CommandError> {
+        spawn(self, false)
+    }
+}
+
+fn spawn(command: &mut Command, show_output: bool) -> Result<CheckedChild, CommandError> {
+    let redact_stdout = show_output && !REDACTED.lock().unwrap().is_empty();
+    if redact_stdout {
+        command.stdout(Stdio::piped());
+    }
+    // In a process group of its own, the command only sees the signals burt forwards to it.
+    if super::interrupt::is_installed() {
+        command.process_group(0);
+    }
+    let mut child = command.stderr(Stdio::piped()).spawn().map_err(|e| CommandError::spawn(command, e))?;
+    super::interrupt::set_child(Some(child.id()));
+    let forwarder = if redact_stdout {
+        let stdout = child.stdout.take().unwrap();
+        Some(std::thread::spawn(move || forward(stdout, Some(io::stdout()), |_| ())))
+    } else {
+        None
+    };
+    let stderr = child.stderr.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut tail = Vec::new();
+        let keep = |data: &[u8]| {
+            tail.extend_from_slice(data);
+            if tail.len() > STDERR_LIMIT {
+                tail.drain(..tail.len() - STDERR_LIMIT);
+            }
+        };
+        forward(stderr, show_output.then(io::stderr), keep);
+        tail
+    });
+
+    Ok(CheckedChild {
+        command: command_line(command),
+        child,
+        reader,
+        forwarder,
+    })
+}
+
+/// Copy `reader` to `writer`, if any, with the values to [`redact`] hidden, handing everything
+/// read to `read` as well.
+///
+/// Lines are written as a whole, so a value is only missed if it is split over lines.
+fn forward(mut reader: impl Read, mut writer: Option<impl Write>, mut read: impl FnMut(&[u8])) {
+    let mut line = Vec::new();
+    let mut buffer = [0; 4096];
+    let mut write = |line: &[u8]| {
+        let Some(writer) = writer.as_mut() else {
+            return;
+        };
+        let _ = if REDACTED.lock().unwrap().is_empty() {
+            writer.write_all(line)
+        } else {
+            writer.write_all(redacted(&String::from_utf8_lossy(line)).as_bytes())
+        };
+        let _ = writer.flush();
+    };
+    while let Ok(n) = reader.read(&mut buffer) {
+        if n == 0 {
+            break;
+        }
+        read(&buffer[..n]);
+        line.extend_from_slice(&buffer[..n]);
+        // Output without line breaks is let through in pieces rather than held back.
+        let end = match line.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None if line.len() > STDERR_LIMIT => line.len(),
+            None => continue,
+        };
+        write(&line[..end]);
+        line.drain(..end);
+    }
+    if !line.is_empty() {
+        write(&line);
+    }
+}
+
+/// A running command whose stderr is being collected.
+pub struct CheckedChild {
+    command: String,
+    child: Child,
+    reader: JoinHandle<Vec<u8>>,
+    /// Passes stdout on with values redacted, if it isn't inherited.
+    forwarder: Option<JoinHandle<()>>,
+}
+
+impl CheckedChild {
+    pub fn child(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    pub fn wait(self) -> Result<(), CommandError> {
+        self.wait_timeout(None)
+    }
+
+    /// Wait for the command, killing it and everything it started once `timeout` has passed.
+    pub fn wait_timeout(mut self, timeout: Option<Duration>) -> Result<(), CommandError> {
+        let status = match timeout {
+            Some(timeout) => self.poll(Instant::now() + timeout),
+            None => self.child.wait().map(Some),
+        };
+        super::interrupt::set_child(None);
+
+        let kind = match status {
+            Ok(Some(status)) => return self.finish(status),
+            // The reader is left behind, as whatever still holds stderr may not have been killed.
+            Ok(None) => CommandErrorKind::Timeout(timeout.unwrap_or_default()),
+            Err(e) => CommandErrorKind::Spawn(e),
+        };
+        Err(CommandError {
+            command: self.command,
+            kind,
+        })
+    }
+
+    /// The exit status, or `None` if the command was killed at `deadline`.
+    fn poll(&mut self, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                unsafe { libc::kill(-(self.child.id() as i32), libc::SIGKILL) };
+                self.child.wait()?;
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn finish(self, status: ExitStatus) -> Result<(), CommandError> {
+        if let Some(forwarder) = self.forwarder {
+            let _ = forwarder.join();
+        }
+        let stderr = self.reader.join().unwrap_or_default();
+        if status.success() {
+            Ok(())
+        } else {
+            Err(CommandError {
+                command: self.command,
+                kind: CommandErrorKind::Status {
+                    status,
+                    stderr: redacted(String::from_utf8_lossy(&stderr).trim_end()),
+                },
+            })
+        }
+    }
+}
+
+fn command_line(command: &Command) -> String {
+    let line = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|a| {
+            let a = a.to_string_lossy();
+            if a.is_empty() || a.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"') {
+                format!("'{}'", a.replace('\'', "'\\''"))
+            } else {
+                a.into_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    redacted(&line)
+}
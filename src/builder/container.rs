@@ -1,118 +1,156 @@
+use std::collections::HashMap;
+use std::ffi::CString;
 use std::fs;
+use std::io::{self, Seek};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::rc::Rc;
 
+use anyhow::Context;
+
+use super::backend::{Backend, ContainerOptions, Network};
+use super::command::CommandExt;
+use super::interrupt;
 use super::ContainerSrc;
 
 pub struct Container {
+    backend: Rc<dyn Backend>,
     container: String,
+    network: Network,
+    /// Removed only after the container, whose mounts keep pointing at them.
+    _sources: Vec<Rc<tempfile::TempDir>>,
 }
 
 impl Container {
-    pub fn create(from: &str) -> anyhow::Result<Self> {
+    pub fn create(backend: &Rc<dyn Backend>, from: &str, options: &ContainerOptions) -> anyhow::Result<Self> {
         if from.is_empty() {
             anyhow::bail!("Invalid image source");
         }
 
-        let out = Command::new("buildah").arg("from").arg(from).output()?;
-        let container = String::from_utf8(out.stdout.trim_ascii_end().to_vec())?;
+        let container = backend.create(from, options)?;
         Ok(Self {
-            container
+            backend: backend.clone(),
+            container,
+            network: options.network,
+            _sources: options.sources.clone(),
         })
     }
 
-    pub fn commit(&self, key: String) -> anyhow::Result<ContainerSrc> {
-        Command::new("buildah")
-            .arg("config")
-            .arg("--created-by").arg(&key)
-            .arg("--label").arg(format!("burt.key={key}"))
-            .arg(&self.container)
-            .status()?;
-
-        let out = Command::new("buildah")
-            .arg("commit")
-            .arg(&self.container)
-            .output()?;
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn backend(&self) -> &Rc<dyn Backend> {
+        &self.backend
+    }
+
+    pub fn commit(&self, key: String, parent: &str, bypass_cache: bool) -> anyhow::Result<ContainerSrc> {
+        let from = self.backend.commit(&self.container, &key, parent)?;
         Ok(super::ContainerSrc {
-            from: String::from_utf8(out.stdout.trim_ascii_end().to_vec())?,
-            key
+            from,
+            key,
+            bypass_cache,
         })
     }
 
-    pub fn run(&self) -> CommandRun {
-        let mut command = Command::new("buildah");
-        command.arg("run").arg("--").arg(&self.container);
-        CommandRun {
-            command
-        }
+    /// Command that runs the arguments appended to it, with `env` added to its environment.
+    pub fn run(&self, env: &[(String, String)]) -> anyhow::Result<CommandRun> {
+        Ok(CommandRun {
+            command: self.backend.run(&self.container, env)?,
+            timeout: None,
+        })
     }
 
     pub fn copy_to_container(&self, src: &str, container: &Container, dest: &str) -> anyhow::Result<()> {
-        let burt = crate::current_exe();
-        Command::new("buildah")
-            .arg("unshare")
-            .arg("-m").arg(format!("PREFIX_SRC={}", &self.container))
-            .arg("-m").arg(format!("PREFIX_DEST={}", &container.container))
-            .arg("--")
-            .arg(burt)
+        self.backend.helper(&[("PREFIX_SRC", &self.container), ("PREFIX_DEST", &container.container)])
             .arg("internal-container-copy")
             .arg(src)
-            .arg(dest).status()?;
+            .arg(dest)
+            .checked_output()?;
         Ok(())
     }
 
-    pub fn set_work_dir(&self, path: &Path) -> anyhow::Result<()> {
-        Command::new("buildah")
-            .arg("config")
-            .arg("--workingdir")
-            .arg(path)
-            .arg(&self.container).status()?;
+    /// Run `/bin/sh` in the container, attached to the terminal, with `env` added to its environment.
+    pub fn shell(&self, env: &[(String, String)]) -> anyhow::Result<()> {
+        let mut command = self.backend.interactive(&self.container, env)?;
+        command.arg("/bin/sh");
+        interrupt::ignored(|| command.status())
+            .context("Failed to start shell")?;
         Ok(())
     }
 
+    pub fn set_work_dir(&self, path: &Path) -> anyhow::Result<()> {
+        self.backend.set_work_dir(&self.container, path)
+    }
+
+    pub fn work_dir(&self) -> anyhow::Result<PathBuf> {
+        self.backend.work_dir(&self.container)
+    }
+
     pub fn export(&self, src: &Path, dest: ExportDestination)-> anyhow::Result<()> {
-        let burt = crate::current_exe();
-        let mut child = Command::new("buildah")
-            .arg("unshare")
-            .arg("-m").arg(format!("PREFIX={}", &self.container))
-            .arg("--")
-            .arg(burt)
-            .arg("internal-export")
+        self.export_with(src, dest, false)
+    }
+
+    /// Like [`Container::export`], but with nothing exported if `src` does not exist.
+    pub fn export_if_exists(&self, src: &Path, dest: ExportDestination)-> anyhow::Result<()> {
+        self.export_with(src, dest, true)
+    }
+
+    fn export_with(&self, src: &Path, dest: ExportDestination, if_exists: bool)-> anyhow::Result<()> {
+        let mut command = self.backend.helper(&[("PREFIX", &self.container)]);
+        command.arg("internal-export");
+        if if_exists {
+            command.arg("--if-exists");
+        }
+        let mut child = command
             .arg(src)
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
-            .spawn()?;
+            .checked_spawn()?;
         
-        let mut stdout = child.stdout.take().unwrap();
-        match dest {
+        let mut stdout = child.child().stdout.take().unwrap();
+        let result = match dest {
             ExportDestination::Path(path) => {
-                let mut archive = tar::Archive::new(stdout);
-                archive.unpack(path)?;
+                let mut archive = tar::Archive::new(&mut stdout);
+                archive.unpack(path)
             }
             ExportDestination::Writer(w) => {
-                std::io::copy(&mut stdout, w)?;
+                std::io::copy(&mut stdout, w).map(|_| ())
             }
-        }
+        };
+        drop(stdout);
 
+        // A failed helper explains itself better than the truncated archive does.
         child.wait()?;
+        result?;
 
         Ok(())
     }
 
+    /// Contents of a file in the container.
+    pub fn read_file(&self, path: &Path) -> anyhow::Result<String> {
+        let mut buffer = io::Cursor::new(Vec::new());
+        self.export(path, ExportDestination::Writer(&mut buffer))?;
+        buffer.set_position(0);
+
+        let mut value = String::new();
+        if let Some(file) = tar::Archive::new(buffer).entries()?.next() {
+            io::Read::read_to_string(&mut file?, &mut value)?;
+        }
+        Ok(value)
+    }
+
     pub fn import_tar<P>(&self, tarfile: std::fs::File, dest: P) -> anyhow::Result<()>
     where 
         P: AsRef<Path>,
     {
-        let burt = crate::current_exe();
-        Command::new("buildah")
-            .arg("unshare")
-            .arg("-m").arg(format!("PREFIX={}", &self.container))
-            .arg("--")
-            .arg(burt)
+        self.backend.helper(&[("PREFIX", &self.container)])
             .arg("internal-import-tar")
             .arg(dest.as_ref())
             .stdin(tarfile)
-            .status()?;
+            .checked_output()?;
 
         Ok(())
     }
@@ -120,27 +158,22 @@ impl Container {
 
 impl Drop for Container {
     fn drop(&mut self) {
-        let _ = delete_container(&self.container);
-    }
-}
-
-fn delete_container(name: &str) -> anyhow::Result<()> {
-    let status = Command::new("buildah")
-        .arg("rm")
-        .arg(name)
-        .status()?;
-    if status.success() {
-        Ok(())
-    } else {
-        anyhow::bail!("Delete container {} failed", name)
+        let _ = self.backend.remove(&self.container);
     }
 }
 
 pub struct CommandRun {
-    command: Command
+    command: Command,
+    timeout: Option<std::time::Duration>,
 }
 
 impl CommandRun {
+    /// Kill the command if it is still running after `timeout`.
+    pub fn timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     #[allow(dead_code)]
     pub fn arg<S>(mut self, arg: S) -> Self
     where
@@ -160,11 +193,11 @@ impl CommandRun {
     }
 
     pub fn output(mut self) -> anyhow::Result<Vec<u8>> {
-        Ok(self.command.output()?.stdout)
+        Ok(self.command.stdin(Stdio::null()).checked_output()?)
     }
 
-    pub fn status(mut self) -> anyhow::Result<std::process::ExitStatus> {
-        Ok(self.command.status()?)
+    pub fn status(mut self) -> anyhow::Result<()> {
+        Ok(self.command.stdin(Stdio::null()).checked_status(self.timeout)?)
     }
 }
 
@@ -173,19 +206,92 @@ pub enum ExportDestination<'a> {
     Writer(&'a mut dyn std::io::Write),
 }
 
-fn copy_dir(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::Result<()> {
-    fs::create_dir_all(&dst)?;
-    for entry in std::fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
-            copy_dir(entry.path(), dst.as_ref().join(entry.file_name()))?;
+/// Copy a tree as it is, keeping ownership, permissions, timestamps and extended
+/// attributes, symlinks as links and files linked within the tree as hardlinks.
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    copy_entry(src, dst, &mut HashMap::new())
+}
+
+/// `links` has the first copy of every file with more than one link, by device and inode.
+fn copy_entry(src: &Path, dst: &Path, links: &mut HashMap<(u64, u64), PathBuf>) -> io::Result<()> {
+    let meta = fs::symlink_metadata(src)?;
+    let ty = meta.file_type();
+    if ty.is_dir() {
+        match fs::symlink_metadata(dst) {
+            Ok(existing) if existing.is_dir() => (),
+            Ok(_) => {
+                fs::remove_file(dst)?;
+                fs::create_dir(dst)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(dst)?,
+            Err(e) => return Err(e),
+        }
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_entry(&entry.path(), &dst.join(entry.file_name()), links)?;
+        }
+    } else {
+        match fs::symlink_metadata(dst) {
+            Ok(existing) if existing.is_dir() => fs::remove_dir_all(dst)?,
+            Ok(_) => fs::remove_file(dst)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        if meta.nlink() > 1 {
+            if let Some(first) = links.get(&(meta.dev(), meta.ino())) {
+                return fs::hard_link(first, dst);
+            }
+            links.insert((meta.dev(), meta.ino()), dst.to_owned());
+        }
+
+        if ty.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
         } else if ty.is_file() {
-            fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
-        } else if ty.is_symlink() {
-            std::os::unix::fs::symlink(dst.as_ref().join(entry.file_name()), entry.path())?;
+            fs::copy(src, dst)?;
+        } else {
+            let path = CString::new(dst.as_os_str().as_bytes())?;
+            if unsafe { libc::mknod(path.as_ptr(), meta.mode(), meta.rdev()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    std::os::unix::fs::lchown(dst, Some(meta.uid()), Some(meta.gid()))?;
+    copy_xattrs(src, dst)?;
+    // After the chown, which clears the setuid and setgid bits.
+    if !ty.is_symlink() {
+        fs::set_permissions(dst, fs::Permissions::from_mode(meta.mode()))?;
+    }
+    // Last, as everything before changes the timestamps of directories.
+    filetime::set_symlink_file_times(
+        dst,
+        filetime::FileTime::from_last_access_time(&meta),
+        filetime::FileTime::from_last_modification_time(&meta),
+    )
+}
+
+/// Extended attributes that can't be set, like `security.selinux` in a user namespace or any of
+/// them on a filesystem without support for them, are left out. That is only mentioned outside
+/// of the `security` namespace, which is managed by the system rather than part of the content.
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    let unsupported = |e: &io::Error| matches!(e.raw_os_error(), Some(libc::EPERM | libc::ENOTSUP));
+    let names = match xattr::list(src) {
+        Err(e) if unsupported(&e) => return Ok(()),
+        rv => rv?,
+    };
+    for name in names {
+        let Some(value) = xattr::get(src, &name)? else {
+            continue;
+        };
+        match xattr::set(dst, &name, &value) {
+            Err(e) if unsupported(&e) => {
+                if !name.as_bytes().starts_with(b"security.") {
+                    eprintln!("Leaving out extended attribute {} of {}: {}", name.to_string_lossy(), dst.display(), e);
+                }
+            }
+            rv => rv?,
         }
-        // TODO: chown, chdir
     }
     Ok(())
 }
@@ -198,30 +304,63 @@ fn internal_container_path(name: &str, suffix: &Path) -> PathBuf {
 }
 
 pub(crate) fn perform_container_copy(src: &Path, dest: &Path) -> Result<(), anyhow::Error> {
-    let src = internal_container_path("PREFIX_SRC", src);
-    let mut dest = internal_container_path("PREFIX_DEST", dest);
-
-    if let Some(filename) = src.file_name() {
-        dest.push(filename);
+    let into_dir = super::copy::names_directory(dest);
+    let pattern = src.to_string_lossy();
+    let sources = if super::copy::is_pattern(&pattern) {
+        let root = internal_container_path("PREFIX_SRC", Path::new("/"));
+        let matches = super::copy::glob_paths(&format!(
+            "{}{}",
+            glob::Pattern::escape(&root.to_string_lossy()),
+            pattern.trim_start_matches('/'),
+        ))?;
+        if matches.is_empty() {
+            anyhow::bail!("SAVE ARTIFACT pattern {} matched nothing", pattern);
+        }
+        matches
+    } else {
+        vec![internal_container_path("PREFIX_SRC", src)]
+    };
+    if sources.len() > 1 && !into_dir {
+        anyhow::bail!("SAVE ARTIFACT {} matched more than one path, so it needs a destination ending with /", pattern);
     }
 
-    if src.is_dir() {
-        let _ = fs::remove_dir_all(&dest);
-        copy_dir(&src, &dest)?;
-    } else {
+    for src in sources {
+        let mut dest = internal_container_path("PREFIX_DEST", dest);
+
+        // A directory is merged into the destination, and a file only put in it if it ends with a slash.
+        if !src.is_dir() && into_dir && let Some(filename) = src.file_name() {
+            dest.push(filename);
+        }
+
         if let Some(parent) = dest.parent() {
             fs::create_dir_all(parent)?;
         }
-        fs::copy(src, dest)?;
+        copy_tree(&src, &dest)?;
     }
 
     Ok(())
 }
 
-pub(crate) fn perform_container_export(path: &Path) -> Result<(), anyhow::Error> {
+pub(crate) fn perform_container_export(path: &Path, if_exists: bool) -> Result<(), anyhow::Error> {
     let prefix = internal_container_path("PREFIX", path);
     let mut tarb = tar::Builder::new(std::io::stdout());
-    tarb.append_dir_all("", prefix)?;
+    // Links are kept as they are, their targets are paths of the container and not of the host.
+    tarb.follow_symlinks(false);
+    let metadata = match fs::symlink_metadata(&prefix) {
+        Err(e) if if_exists && e.kind() == io::ErrorKind::NotFound => {
+            tarb.finish()?;
+            return Ok(());
+        }
+        metadata => metadata.map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?,
+    };
+    if metadata.is_dir() {
+        // Named like a file would be, so that the two can be told apart.
+        let name = path.file_name().map(ToOwned::to_owned).unwrap_or_default();
+        tarb.append_dir_all(name, &prefix)?;
+    } else {
+        let name = prefix.file_name().ok_or_else(|| anyhow::anyhow!("Invalid export path"))?.to_owned();
+        tarb.append_path_with_name(&prefix, name)?;
+    }
     tarb.finish()?;
     Ok(())
 }
@@ -231,32 +370,102 @@ where
     R: std::io::Read
 {
     let prefix = internal_container_path("PREFIX", path);
-    let mut tarf = tar::Archive::new(reader);
-    tarf.unpack(prefix)?;
-    Ok(())
+    // Only root can give files away, which the helper is when the backend runs it in a user namespace.
+    unpack_owned(reader, &prefix, unsafe { libc::geteuid() } == 0)
 }
 
-pub(crate) fn fetch_image(name: &str) -> anyhow::Result<String> {
-    let out = Command::new("buildah")
-        .arg("pull")
-        .arg(name)
-        .output()?;
-    
-    Ok(String::from_utf8(out.stdout.trim_ascii_end().to_vec())?)
+/// Unpack a tar keeping the owners of its entries, failing without unpacking anything if that
+/// takes giving files away but `can_chown` is false.
+///
+/// Files owned by root are fine either way, as whoever unpacks them is root in the container.
+fn unpack_owned<R: std::io::Read>(mut reader: R, dest: &Path, can_chown: bool) -> anyhow::Result<()> {
+    if can_chown {
+        let mut tarf = tar::Archive::new(reader);
+        tarf.set_preserve_ownerships(true);
+        tarf.unpack(dest)?;
+        return Ok(());
+    }
+
+    // The entries are all checked before unpacking any, so the archive is read twice.
+    let mut file = tempfile::tempfile()?;
+    io::copy(&mut reader, &mut file)?;
+    file.rewind()?;
+    for entry in tar::Archive::new(&file).entries()? {
+        let entry = entry?;
+        let (uid, gid) = (entry.header().uid()?, entry.header().gid()?);
+        if uid != 0 || gid != 0 {
+            anyhow::bail!("Can't give {} to {}:{} without running as root", entry.path()?.display(), uid, gid);
+        }
+    }
+    file.rewind()?;
+    tar::Archive::new(file).unpack(dest)?;
+    Ok(())
 }
 
-pub(crate) fn get_cached_image(key: &str) -> Option<String> {
-    #[derive(serde::Deserialize)]
-    struct Image {
-        image: String
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_tree() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let dir = src.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file"), "data").unwrap();
+        fs::set_permissions(dir.join("file"), fs::Permissions::from_mode(0o751)).unwrap();
+        fs::hard_link(dir.join("file"), dir.join("link")).unwrap();
+        std::os::unix::fs::symlink("file", dir.join("symlink")).unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_000_000, 0);
+        filetime::set_file_mtime(dir.join("file"), mtime).unwrap();
+        filetime::set_file_mtime(&dir, mtime).unwrap();
+
+        let copy = dst.path().join("dir");
+        copy_tree(&dir, &copy).unwrap();
+
+        let file = fs::metadata(copy.join("file")).unwrap();
+        assert_eq!(fs::read_to_string(copy.join("file")).unwrap(), "data");
+        assert_eq!(file.mode() & 0o7777, 0o751);
+        assert_eq!(filetime::FileTime::from_last_modification_time(&file), mtime);
+        assert_eq!(fs::metadata(copy.join("link")).unwrap().ino(), file.ino());
+        assert_eq!(fs::read_link(copy.join("symlink")).unwrap(), Path::new("file"));
+        assert_eq!(filetime::FileTime::from_last_modification_time(&fs::metadata(&copy).unwrap()), mtime);
     }
 
-    let out = Command::new("buildah")
-        .arg("images")
-        .arg("--json")
-        .arg("--filter")
-        .arg(format!("label=burt.key={key}"))
-        .output().ok()?;
-    let images: Vec<Image> = serde_json::from_slice(&out.stdout).ok()?;
-    images.into_iter().next().map(|i| i.image)
+    #[test]
+    fn test_copy_tree_replaces() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("was-dir"), "file").unwrap();
+        fs::create_dir(src.path().join("was-file")).unwrap();
+        fs::create_dir_all(dst.path().join("was-dir/sub")).unwrap();
+        fs::write(dst.path().join("was-file"), "file").unwrap();
+
+        copy_tree(src.path(), dst.path()).unwrap();
+
+        assert_eq!(fs::read_to_string(dst.path().join("was-dir")).unwrap(), "file");
+        assert!(dst.path().join("was-file").is_dir());
+    }
+
+    #[test]
+    fn test_unpack_owned() {
+        let tar = |uid| {
+            let mut builder = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.set_uid(uid);
+            header.set_gid(0);
+            builder.append_data(&mut header, "file", &b"data"[..]).unwrap();
+            builder.into_inner().unwrap()
+        };
+        let dst = tempfile::tempdir().unwrap();
+
+        unpack_owned(&tar(0)[..], dst.path(), false).unwrap();
+        assert_eq!(fs::read_to_string(dst.path().join("file")).unwrap(), "data");
+
+        let err = unpack_owned(&tar(1000)[..], &dst.path().join("other"), false).unwrap_err();
+        assert!(err.to_string().contains("Can't give file to 1000:0"), "{err}");
+        assert!(!dst.path().join("other").exists());
+    }
 }
@@ -0,0 +1,586 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::collections::HashMap;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use base64::prelude::*;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use sha2::Digest;
+
+use super::hashfile::{HashedFile, HashedReader};
+use super::statcache::{FileStat, StatCache};
+
+/// A set of files to copy into a container.
+///
+/// Only the path, mode and content of each entry contribute to the key, and
+/// the tar written for the container is normalised the same way, so that
+/// timestamps and ownership on the host don't invalidate cached steps.
+/// Files linked to each other stay hardlinks, which is part of the key too.
+#[derive(Default)]
+pub struct CopyArchive {
+    entries: Vec<CopyEntry>,
+    tempdirs: Vec<tempfile::TempDir>,
+    /// User and group of every entry, root unless set.
+    owner: Option<(u64, u64)>,
+    /// Mode of every entry but symlinks, instead of the mode of its source.
+    mode: Option<u32>,
+    /// Local files left out, along with everything below them.
+    ignore: Option<Rc<Gitignore>>,
+}
+
+struct CopyEntry {
+    name: PathBuf,
+    source: PathBuf,
+    kind: EntryKind,
+    mode: u32,
+    digest: Vec<u8>,
+    /// Set for local files whose digest is still to be computed.
+    stat: Option<FileStat>,
+    /// Device and inode of files with more than one link, to find the other names of the file.
+    inode: Option<(u64, u64)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// Another name of an earlier file, whose name is in the digest.
+    Hardlink,
+}
+
+impl CopyArchive {
+    /// Add `source` from the local filesystem, and everything below it, as `name`.
+    pub fn add_path(&mut self, source: &Path, name: &Path) -> io::Result<()> {
+        let source = std::path::absolute(source)?;
+        self.add_entries(&source, name, true)
+    }
+
+    pub fn set_owner(&mut self, uid: u64, gid: u64) {
+        self.owner = Some((uid, gid));
+    }
+
+    pub fn set_mode(&mut self, mode: u32) {
+        self.mode = Some(mode);
+    }
+
+    /// Leave out local files matching `ignore`, which must be set before adding them.
+    pub fn set_ignore(&mut self, ignore: Rc<Gitignore>) {
+        self.ignore = Some(ignore);
+    }
+
+    /// Unpack a tar stream, such as an exported artifact, into a directory kept
+    /// as long as the archive, for its contents to be added with [`CopyArchive::add_unpacked`].
+    pub fn unpack<R: Read>(&mut self, reader: R) -> io::Result<PathBuf> {
+        let tempdir = tempfile::tempdir()?;
+        tar::Archive::new(reader).unpack(tempdir.path())?;
+        let path = tempdir.path().to_owned();
+        self.tempdirs.push(tempdir);
+        Ok(path)
+    }
+
+    /// Add `source` from a directory returned by [`CopyArchive::unpack`], and everything below it, as `name`.
+    pub fn add_unpacked(&mut self, source: &Path, name: &Path) -> io::Result<()> {
+        self.add_entries(source, name, false)
+    }
+
+    fn add_entries(&mut self, source: &Path, name: &Path, local: bool) -> io::Result<()> {
+        let meta = fs::symlink_metadata(source)?;
+        let mode = meta.permissions().mode() & 0o7777;
+
+        if local && let Some(ignore) = &self.ignore
+            && source.starts_with(ignore.path())
+            // A path given on its own is still ignored when a directory it is in is.
+            && ignore.matched_path_or_any_parents(source, meta.is_dir()).is_ignore()
+        {
+            return Ok(());
+        }
+
+        if meta.is_symlink() {
+            let target = fs::read_link(source)?;
+            self.push(name, source, EntryKind::Symlink, mode, target.into_os_string().into_encoded_bytes(), None);
+        } else if meta.is_dir() {
+            if name != Path::new("") {
+                self.push(name, source, EntryKind::Dir, mode, Vec::new(), None);
+            }
+            for entry in fs::read_dir(source)? {
+                let entry = entry?;
+                self.add_entries(&entry.path(), &name.join(entry.file_name()), local)?;
+            }
+        } else if meta.is_file() {
+            let stat = FileStat::new(&meta);
+            if local {
+                self.push(name, source, EntryKind::File, mode, Vec::new(), Some(stat));
+            } else {
+                let digest = hash_file(source)?;
+                self.push(name, source, EntryKind::File, mode, digest, None);
+            }
+            if meta.nlink() > 1 {
+                self.entries.last_mut().unwrap().inode = Some((meta.dev(), meta.ino()));
+            }
+        } else {
+            let kind = if meta.file_type().is_socket() { "a socket" } else { "a device or fifo" };
+            return Err(io::Error::other(format!("{} is {}, which can't be copied", source.display(), kind)));
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, name: &Path, source: &Path, kind: EntryKind, mode: u32, digest: Vec<u8>, stat: Option<FileStat>) {
+        self.entries.push(CopyEntry {
+            name: name.to_owned(),
+            source: source.to_owned(),
+            kind,
+            mode,
+            digest,
+            stat,
+            inode: None,
+        });
+    }
+
+    /// Sort the entries by name, and turn every file but the first of those sharing an inode
+    /// into a hardlink to it.
+    fn sort(&mut self) {
+        self.entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut first_names = HashMap::new();
+        for entry in &mut self.entries {
+            let Some(inode) = entry.inode.filter(|_| entry.kind == EntryKind::File) else {
+                continue;
+            };
+            if let Some(first) = first_names.get(&inode) {
+                entry.kind = EntryKind::Hardlink;
+                entry.digest = Path::new(first).as_os_str().as_encoded_bytes().to_vec();
+            } else {
+                first_names.insert(inode, entry.name.clone());
+            }
+        }
+    }
+
+    /// Fill in the digest of local files, reusing `stats` for unchanged files
+    /// and hashing the rest in parallel.
+    fn hash_contents(&mut self, stats: &mut StatCache) -> io::Result<()> {
+        let mut pending = Vec::new();
+        for (idx, entry) in self.entries.iter_mut().enumerate() {
+            let Some(stat) = entry.stat.as_ref() else {
+                continue;
+            };
+            if let Some(digest) = stats.get(&entry.source, stat) {
+                entry.digest = digest;
+                entry.stat = None;
+            } else {
+                pending.push(idx);
+            }
+        }
+
+        let paths: Vec<&Path> = pending.iter().map(|idx| self.entries[*idx].source.as_path()).collect();
+        let digests = hash_files(&paths)?;
+
+        for (idx, digest) in pending.into_iter().zip(digests) {
+            let entry = &mut self.entries[idx];
+            if let Some(stat) = entry.stat.take() {
+                stats.insert(entry.source.clone(), stat, &digest);
+            }
+            entry.digest = digest;
+        }
+
+        Ok(())
+    }
+
+    /// Cache key over the name, mode and content of every entry.
+    pub fn key(&mut self, stats: &mut StatCache) -> io::Result<String> {
+        self.hash_contents(stats)?;
+        self.sort();
+
+        let mut hash = sha2::Sha256::new();
+        for entry in &self.entries {
+            let kind = match entry.kind {
+                EntryKind::File => b'f',
+                EntryKind::Dir => b'd',
+                EntryKind::Symlink => b'l',
+                EntryKind::Hardlink => b'h',
+            };
+            hash.update(entry.name.as_os_str().as_encoded_bytes());
+            hash.update([0, kind]);
+            hash.update(entry.mode.to_le_bytes());
+            hash.update(&entry.digest);
+            hash.update(b"\0");
+        }
+        Ok(BASE64_STANDARD.encode(hash.finalize()))
+    }
+
+    /// Write the entries as a tar with fixed timestamps and ownership.
+    ///
+    /// Files are hashed again as they are written, failing if one no longer has the content
+    /// [`CopyArchive::key`] was computed from.
+    pub fn write_tar<W: Write>(&mut self, writer: W) -> io::Result<W> {
+        self.sort();
+
+        let (uid, gid) = self.owner.unwrap_or((0, 0));
+        let mut tarb = tar::Builder::new(writer);
+        for entry in &self.entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(match entry.kind {
+                EntryKind::Symlink => entry.mode,
+                _ => self.mode.unwrap_or(entry.mode),
+            });
+            header.set_mtime(0);
+            header.set_uid(uid);
+            header.set_gid(gid);
+
+            match entry.kind {
+                EntryKind::File => {
+                    let file = fs::File::open(&entry.source)?;
+                    let len = file.metadata()?.len();
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(len);
+                    let mut hashed = HashedReader::new(file.take(len));
+                    tarb.append_data(&mut header, &entry.name, &mut hashed)?;
+                    if hashed.finish() != entry.digest {
+                        return Err(io::Error::other(format!("{} changed while it was copied", entry.source.display())));
+                    }
+                }
+                EntryKind::Dir => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    tarb.append_data(&mut header, &entry.name, io::empty())?;
+                }
+                EntryKind::Symlink => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    tarb.append_link(&mut header, &entry.name, fs::read_link(&entry.source)?)?;
+                }
+                EntryKind::Hardlink => {
+                    header.set_entry_type(tar::EntryType::Link);
+                    header.set_size(0);
+                    tarb.append_link(&mut header, &entry.name, Path::new(std::ffi::OsStr::from_bytes(&entry.digest)))?;
+                }
+            }
+        }
+
+        tarb.into_inner()
+    }
+}
+
+/// The `.burtignore` of the burt files in `dir`, matching nothing if there is none.
+pub fn load_ignore(dir: &Path) -> anyhow::Result<Gitignore> {
+    let dir = std::path::absolute(dir)?;
+    let path = dir.join(".burtignore");
+    let mut builder = GitignoreBuilder::new(&dir);
+    if path.exists() && let Some(e) = builder.add(&path) {
+        anyhow::bail!("Failed to read {}: {}", path.display(), e);
+    }
+    Ok(builder.build()?)
+}
+
+/// Whether a source is a shell-style pattern rather than a literal path.
+pub fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Paths matching a shell-style `pattern`, in which `**` matches any number of directories.
+pub fn glob_paths(pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let paths = glob::glob(pattern).map_err(|e| anyhow::anyhow!("Invalid pattern {}: {}", pattern, e))?;
+    Ok(paths.collect::<Result<_, _>>()?)
+}
+
+/// A path relative to `dir`, the directory of a burt file, without `.` components, refusing those
+/// that lead out of it unless `allow_outside` is set. Absolute paths are outside of it too unless
+/// they are below `dir`, and are kept absolute when allowed.
+pub fn context_path(dir: &Path, path: &Path, allow_outside: bool) -> anyhow::Result<PathBuf> {
+    if path.is_absolute() {
+        let absolute = std::path::absolute(path)?;
+        return match absolute.strip_prefix(std::path::absolute(dir)?) {
+            Ok(relative) => context_path(dir, relative, allow_outside),
+            Err(_) if allow_outside => Ok(absolute),
+            Err(_) => Err(outside_context(path)),
+        };
+    }
+
+    let mut relative = PathBuf::new();
+    let mut depth = 0;
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                relative.push(name);
+                depth += 1;
+            }
+            Component::ParentDir if depth > 0 => {
+                relative.pop();
+                depth -= 1;
+            }
+            Component::ParentDir if allow_outside => relative.push(".."),
+            Component::ParentDir => return Err(outside_context(path)),
+            _ => (),
+        }
+    }
+    Ok(relative)
+}
+
+fn outside_context(path: &Path) -> anyhow::Error {
+    anyhow::anyhow!("{} is outside the directory of the burt file, use --allow-outside-context to allow it", path.display())
+}
+
+/// The pattern matching a source given as the pattern `relative`, a [`context_path`] of `dir`.
+pub fn context_pattern(dir: &Path, relative: &Path) -> String {
+    if relative.is_absolute() {
+        relative.to_string_lossy().into_owned()
+    } else {
+        format!("{}/{}", glob::Pattern::escape(&dir.to_string_lossy()), relative.display())
+    }
+}
+
+/// Name of a path below the destination of `COPY --parents`: relative, without `.` components.
+pub fn parents_name(path: &Path) -> PathBuf {
+    path.components().filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir)).collect()
+}
+
+/// Find `name` in the contents of `/etc/passwd` or `/etc/group`, returning the
+/// numeric id and, for a user, the id of its primary group.
+pub fn lookup_id(database: &str, name: &str) -> Option<(u64, Option<u64>)> {
+    database.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        let id = fields.nth(1)?.parse().ok()?;
+        Some((id, fields.next().and_then(|gid| gid.parse().ok())))
+    })
+}
+
+/// The permission bits of a `--chmod` value, which is octal.
+pub fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+    u32::from_str_radix(mode, 8).ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| anyhow::anyhow!("Invalid mode {}, it has to be octal and at most 7777", mode))
+}
+
+/// Whether a destination is a directory to copy into rather than the path of
+/// the copy, which is when it ends with a slash, like in a Dockerfile.
+pub fn names_directory(dest: &Path) -> bool {
+    let bytes = dest.as_os_str().as_bytes();
+    bytes.ends_with(b"/") || bytes.ends_with(b"/.") || dest.file_name().is_none()
+}
+
+fn hash_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut hashed = HashedFile::new(io::sink());
+    io::copy(&mut fs::File::open(path)?, &mut hashed)?;
+    Ok(hashed.finish()?.1)
+}
+
+fn hash_files(paths: &[&Path]) -> io::Result<Vec<Vec<u8>>> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len());
+    let next = AtomicUsize::new(0);
+
+    let results = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+            let mut digests = Vec::new();
+            loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(idx) else {
+                    break;
+                };
+                digests.push((idx, hash_file(path)?));
+            }
+            io::Result::Ok(digests)
+        })).collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    let mut digests = vec![Vec::new(); paths.len()];
+    for result in results {
+        for (idx, digest) in result? {
+            digests[idx] = digest;
+        }
+    }
+    Ok(digests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_of(path: &Path) -> String {
+        let mut archive = CopyArchive::default();
+        archive.add_path(path, Path::new("src")).unwrap();
+        archive.key(&mut StatCache::default()).unwrap()
+    }
+
+    #[test]
+    fn test_key_ignores_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "hello").unwrap();
+        let before = key_of(dir.path());
+
+        fs::File::options().write(true).open(&file).unwrap()
+            .set_modified(std::time::UNIX_EPOCH).unwrap();
+        assert_eq!(before, key_of(dir.path()));
+
+        fs::write(&file, "bye").unwrap();
+        assert_ne!(before, key_of(dir.path()));
+
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+        let executable = key_of(dir.path());
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_ne!(executable, key_of(dir.path()));
+    }
+
+    #[test]
+    fn test_hardlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "data").unwrap();
+        fs::write(dir.path().join("c"), "data").unwrap();
+        let separate = key_of(dir.path());
+        fs::remove_file(dir.path().join("c")).unwrap();
+        fs::hard_link(dir.path().join("a"), dir.path().join("c")).unwrap();
+        assert_ne!(separate, key_of(dir.path()));
+
+        let mut archive = CopyArchive::default();
+        archive.add_path(dir.path(), Path::new("")).unwrap();
+        archive.key(&mut StatCache::default()).unwrap();
+        let tar = archive.write_tar(Vec::new()).unwrap();
+        let out = tempfile::tempdir().unwrap();
+        tar::Archive::new(tar.as_slice()).unpack(out.path()).unwrap();
+        let ino = |name| fs::metadata(out.path().join(name)).unwrap().ino();
+        assert_eq!(ino("a"), ino("c"));
+        assert_eq!(fs::read_to_string(out.path().join("c")).unwrap(), "data");
+    }
+
+    #[test]
+    fn test_special_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = std::ffi::CString::new(dir.path().join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+        let mut archive = CopyArchive::default();
+        let err = archive.add_path(dir.path(), Path::new("")).unwrap_err();
+        assert!(err.to_string().contains("can't be copied"), "{err}");
+    }
+
+    #[test]
+    fn test_changed_while_copied() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "before").unwrap();
+        let mut archive = CopyArchive::default();
+        archive.add_path(dir.path(), Path::new("")).unwrap();
+        archive.key(&mut StatCache::default()).unwrap();
+        fs::write(dir.path().join("a"), "after!").unwrap();
+        let err = archive.write_tar(Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("changed while it was copied"), "{err}");
+    }
+
+    #[test]
+    fn test_ignore() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        fs::create_dir_all(dir.path().join("logs")).unwrap();
+        fs::write(dir.path().join("target/debug/burt"), "").unwrap();
+        fs::write(dir.path().join("logs/a.log"), "").unwrap();
+        fs::write(dir.path().join("logs/keep.log"), "").unwrap();
+        fs::write(dir.path().join("main.rs"), "").unwrap();
+        fs::write(dir.path().join(".burtignore"), "target/\n*.log\n!keep.log\n").unwrap();
+
+        let mut archive = CopyArchive::default();
+        archive.set_ignore(Rc::new(load_ignore(dir.path()).unwrap()));
+        archive.add_path(dir.path(), Path::new("")).unwrap();
+        let mut names: Vec<_> = archive.entries.iter().map(|e| e.name.clone()).collect();
+        names.sort();
+        assert_eq!(names, [".burtignore", "logs", "logs/keep.log", "main.rs"].map(PathBuf::from));
+
+        let key = archive.key(&mut StatCache::default()).unwrap();
+        fs::write(dir.path().join("target/debug/burt"), "changed").unwrap();
+        let mut archive = CopyArchive::default();
+        archive.set_ignore(Rc::new(load_ignore(dir.path()).unwrap()));
+        archive.add_path(dir.path(), Path::new("")).unwrap();
+        assert_eq!(key, archive.key(&mut StatCache::default()).unwrap());
+
+        let mut archive = CopyArchive::default();
+        archive.set_ignore(Rc::new(load_ignore(dir.path()).unwrap()));
+        archive.add_path(&dir.path().join("target/debug/burt"), Path::new("burt")).unwrap();
+        archive.add_path(&dir.path().join("target/debug"), Path::new("debug")).unwrap();
+        assert!(archive.entries.is_empty());
+    }
+
+    #[test]
+    fn test_glob_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/sub")).unwrap();
+        fs::write(dir.path().join("src/a.rs"), "").unwrap();
+        fs::write(dir.path().join("src/sub/b.rs"), "").unwrap();
+        fs::write(dir.path().join("src/c.txt"), "").unwrap();
+
+        let pattern = format!("{}/src/**/*.rs", glob::Pattern::escape(&dir.path().to_string_lossy()));
+        assert_eq!(glob_paths(&pattern).unwrap(), vec![dir.path().join("src/a.rs"), dir.path().join("src/sub/b.rs")]);
+        assert!(glob_paths(&format!("{}/*.so", dir.path().display())).unwrap().is_empty());
+        assert!(is_pattern("src/**/*.rs"));
+        assert!(!is_pattern("src/a.rs"));
+    }
+
+    #[test]
+    fn test_context_path() {
+        let dir = Path::new("/work/project");
+        assert_eq!(context_path(dir, Path::new("./src/../lib/a.rs"), false).unwrap(), Path::new("lib/a.rs"));
+        assert_eq!(context_path(dir, Path::new("."), false).unwrap(), Path::new(""));
+        assert!(context_path(dir, Path::new("src/../../a.rs"), false).is_err());
+        assert_eq!(context_path(dir, Path::new("src/../../a.rs"), true).unwrap(), Path::new("../a.rs"));
+        assert_eq!(context_path(dir, Path::new("/work/project/src/a.rs"), false).unwrap(), Path::new("src/a.rs"));
+        assert!(context_path(dir, Path::new("/work/project/../a.rs"), false).is_err());
+        assert!(context_path(dir, Path::new("/etc/hosts"), false).is_err());
+        assert_eq!(context_path(dir, Path::new("/etc/hosts"), true).unwrap(), Path::new("/etc/hosts"));
+    }
+
+    #[test]
+    fn test_context_pattern() {
+        let dir = Path::new("/work/[project]");
+        assert_eq!(context_pattern(dir, Path::new("src/*.rs")), "/work/[[]project[]]/src/*.rs");
+        assert_eq!(context_pattern(dir, Path::new("/etc/*.conf")), "/etc/*.conf");
+    }
+
+    #[test]
+    fn test_lookup_id() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\napp:x:1000:1001::/home/app:/bin/sh\n";
+        assert_eq!(lookup_id(passwd, "app"), Some((1000, Some(1001))));
+        assert_eq!(lookup_id(passwd, "nobody"), None);
+        assert_eq!(lookup_id("wheel:x:10:root,app\n", "wheel"), Some((10, None)));
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0640").unwrap(), 0o640);
+        assert_eq!(parse_mode("4755").unwrap(), 0o4755);
+        assert!(parse_mode("10000").is_err());
+        assert!(parse_mode("0o644").is_err());
+        assert!(parse_mode("u+x").is_err());
+    }
+
+    #[test]
+    fn test_names_directory() {
+        assert!(names_directory(Path::new("/")));
+        assert!(names_directory(Path::new("/etc/")));
+        assert!(names_directory(Path::new(".")));
+        assert!(names_directory(Path::new("etc/.")));
+        assert!(names_directory(Path::new("..")));
+        assert!(!names_directory(Path::new("/etc/b.txt")));
+        assert!(!names_directory(Path::new("b")));
+    }
+
+    #[test]
+    fn test_key_fills_stat_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "hello").unwrap();
+
+        let mut stats = StatCache::default();
+        let mut archive = CopyArchive::default();
+        archive.add_path(dir.path(), Path::new("src")).unwrap();
+        let key = archive.key(&mut stats).unwrap();
+
+        let stat = FileStat::new(&fs::metadata(&file).unwrap());
+        assert_eq!(stats.get(&file, &stat), Some(hash_file(&file).unwrap()));
+        assert_eq!(key, key_of(dir.path()));
+    }
+}
@@ -33,3 +33,30 @@ impl<W> io::Write for HashedFile<W> where W: io::Write {
         self.writer.flush()
     }
 }
+
+/// Like [`HashedFile`], but hashing what is read instead of what is written.
+pub struct HashedReader<R> {
+    reader: R,
+    hash: sha2::Sha256
+}
+
+impl<R> HashedReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            hash: sha2::Sha256::new()
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.hash.finalize().to_vec()
+    }
+}
+
+impl<R> io::Read for HashedReader<R> where R: io::Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        self.hash.update(&buf[..len]);
+        Ok(len)
+    }
+}
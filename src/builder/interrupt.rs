@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+/// The first SIGINT or SIGTERM received, or 0.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Process group of the command currently running, or 0.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Set once `install` has taken over SIGINT and SIGTERM.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Set while an interactive command has the terminal, which handles signals itself.
+static IGNORED: AtomicBool = AtomicBool::new(false);
+
+/// Catch SIGINT and SIGTERM so that the build can stop and remove its containers.
+///
+/// The signal is forwarded to the running command and everything it started,
+/// and the build fails at its next step. A second signal exits immediately.
+pub fn install() -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if IGNORED.load(Ordering::SeqCst) {
+                continue;
+            }
+            if SIGNAL.swap(signal, Ordering::SeqCst) != 0 {
+                std::process::exit(128 + signal);
+            }
+            let child = CHILD.load(Ordering::SeqCst);
+            if child != 0 {
+                unsafe { libc::kill(-child, signal) };
+            }
+        }
+    });
+    INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// The signal that interrupted the build, if any.
+pub fn interrupted() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Run `func` without treating SIGINT and SIGTERM as interrupting the build.
+pub fn ignored<T>(func: impl FnOnce() -> T) -> T {
+    IGNORED.store(true, Ordering::SeqCst);
+    let rv = func();
+    IGNORED.store(false, Ordering::SeqCst);
+    rv
+}
+
+pub fn check() -> anyhow::Result<()> {
+    if interrupted().is_some() {
+        anyhow::bail!("Interrupted");
+    }
+    Ok(())
+}
+
+/// Whether signals are forwarded to commands, which otherwise get them from the terminal with burt.
+pub(super) fn is_installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+pub(super) fn set_child(pid: Option<u32>) {
+    CHILD.store(pid.map_or(0, |p| p as i32), Ordering::SeqCst);
+}
+
+/// Whether the process that created a container is still running.
+pub(super) fn is_running(pid: u32) -> bool {
+    let rv = unsafe { libc::kill(pid as i32, 0) };
+    rv == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::backend::{Backend, PullPolicy};
+
+/// An image named by a FROM command, with the platform it is built for.
+pub type ImageRef = (String, Option<String>);
+
+/// The digests `burt lock` pinned the images of the FROM commands of a burt file to.
+///
+/// They are kept in `burt.lock` next to the burt file, under its name, so that the burt files of
+/// a directory share one lock file without overwriting each other's pins.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LockFile {
+    images: Vec<LockedImage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LockedImage {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    platform: Option<String>,
+    digest: String,
+}
+
+impl LockFile {
+    /// The lock file of a burt file, which is next to it.
+    pub fn path(burt_file: &Path) -> PathBuf {
+        burt_file.with_file_name("burt.lock")
+    }
+
+    /// Read the pins of `burt_file`, if it has any.
+    pub fn load(burt_file: &Path) -> anyhow::Result<Option<Self>> {
+        Ok(read_pins(&Self::path(burt_file))?.remove(&file_name(burt_file)))
+    }
+
+    /// Replace the pins of `burt_file`, keeping those of the other burt files.
+    pub fn save(self, burt_file: &Path) -> anyhow::Result<()> {
+        let path = Self::path(burt_file);
+        let mut pins = read_pins(&path)?;
+        pins.insert(file_name(burt_file), self);
+        let mut data = serde_json::to_string_pretty(&pins)?;
+        data.push('\n');
+        std::fs::write(&path, data)
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Fetch every image and pin it to the digest it has now.
+    pub fn resolve(backend: &dyn Backend, images: &BTreeSet<ImageRef>, pull: PullPolicy) -> anyhow::Result<Self> {
+        let images = images.iter()
+            .filter(|(name, _)| !is_pinned(name))
+            .map(|(name, platform)| {
+                let id = super::build::fetch_image(backend, name, platform.as_deref(), pull)?;
+                let digest = backend.image_digests(&id)?.into_iter().next()
+                    .ok_or_else(|| anyhow::anyhow!("Image {} has no digest", name))?;
+                Ok(LockedImage {
+                    name: name.clone(),
+                    platform: platform.clone(),
+                    digest,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { images })
+    }
+
+    /// Keep the pins of `previous` for images that are still `used` but weren't resolved again.
+    pub fn keep(&mut self, previous: &LockFile, used: &BTreeSet<ImageRef>) {
+        let kept: Vec<_> = previous.images.iter()
+            .filter(|i| used.contains(&i.image_ref()) && self.digest(&i.name, i.platform.as_deref()).is_none())
+            .cloned()
+            .collect();
+        self.images.extend(kept);
+        self.images.sort_by(|a, b| (&a.name, &a.platform).cmp(&(&b.name, &b.platform)));
+    }
+
+    /// Fail if the pins don't match the images the burt file is built from, listing the images
+    /// that have no pin and the pins of images that are no longer used.
+    pub fn check(&self, images: &BTreeSet<ImageRef>) -> anyhow::Result<()> {
+        let missing = images.iter()
+            .filter(|(name, platform)| !is_pinned(name) && self.digest(name, platform.as_deref()).is_none())
+            .map(|(name, platform)| format!("\n  missing {}", describe(name, platform.as_deref())));
+        let unused = self.images.iter()
+            .filter(|i| !images.contains(&i.image_ref()))
+            .map(|i| format!("\n  unused {}", describe(&i.name, i.platform.as_deref())));
+        let problems: String = missing.chain(unused).collect();
+        if !problems.is_empty() {
+            anyhow::bail!("Pinned images don't match the burt file:{}", problems);
+        }
+        Ok(())
+    }
+
+    pub fn digest(&self, name: &str, platform: Option<&str>) -> Option<&str> {
+        self.images.iter()
+            .find(|i| i.name == name && i.platform.as_deref() == platform)
+            .map(|i| i.digest.as_str())
+    }
+}
+
+impl LockedImage {
+    fn image_ref(&self) -> ImageRef {
+        (self.name.clone(), self.platform.clone())
+    }
+}
+
+impl std::fmt::Display for LockFile {
+    /// One line for each pin, with the image and its digest.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for image in &self.images {
+            writeln!(f, "{} {}", describe(&image.name, image.platform.as_deref()), image.digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// The pins of every burt file in the lock file at `path`.
+fn read_pins(path: &Path) -> anyhow::Result<BTreeMap<String, LockFile>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+    };
+    serde_json::from_slice(&data)
+        .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))
+}
+
+fn file_name(burt_file: &Path) -> String {
+    burt_file.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn describe(name: &str, platform: Option<&str>) -> String {
+    match platform {
+        Some(platform) => format!("{name} ({platform})"),
+        None => name.to_owned(),
+    }
+}
+
+/// Whether the image needs no digest from the lock file, as it already names one or is the empty image.
+pub fn is_pinned(name: &str) -> bool {
+    name == "scratch" || name.contains('@')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let lock = LockFile {
+            images: vec![
+                LockedImage { name: "alpine:3".to_owned(), platform: None, digest: "sha256:aa".to_owned() },
+                LockedImage { name: "alpine:3".to_owned(), platform: Some("linux/arm64".to_owned()), digest: "sha256:bb".to_owned() },
+            ],
+        };
+        let other = LockFile { images: vec![locked("busybox", "sha256:cc")] };
+        let dir = tempfile::tempdir().unwrap();
+        let burt_file = dir.path().join("build.burt");
+        assert_eq!(LockFile::load(&burt_file).unwrap(), None);
+        lock.save(&burt_file).unwrap();
+        other.save(&dir.path().join("other.burt")).unwrap();
+        assert_eq!(LockFile::path(&burt_file), dir.path().join("burt.lock"));
+
+        let loaded = LockFile::load(&burt_file).unwrap().unwrap();
+        assert_eq!(loaded.digest("alpine:3", None), Some("sha256:aa"));
+        assert_eq!(loaded.digest("alpine:3", Some("linux/arm64")), Some("sha256:bb"));
+        assert_eq!(loaded.digest("alpine:3", Some("linux/amd64")), None);
+        assert_eq!(loaded.digest("busybox", None), None);
+        let loaded = LockFile::load(&dir.path().join("other.burt")).unwrap().unwrap();
+        assert_eq!(loaded.digest("busybox", None), Some("sha256:cc"));
+        assert_eq!(LockFile::load(&dir.path().join("missing.burt")).unwrap(), None);
+    }
+
+    fn locked(name: &str, digest: &str) -> LockedImage {
+        LockedImage { name: name.to_owned(), platform: None, digest: digest.to_owned() }
+    }
+
+    fn refs(names: &[&str]) -> BTreeSet<ImageRef> {
+        names.iter().map(|n| (n.to_string(), None)).collect()
+    }
+
+    #[test]
+    fn test_keep() {
+        let previous = LockFile { images: vec![locked("busybox", "sha256:aa"), locked("debian", "sha256:bb"), locked("gone", "sha256:cc")] };
+        let mut lock = LockFile { images: vec![locked("alpine", "sha256:dd"), locked("debian", "sha256:ee")] };
+        lock.keep(&previous, &refs(&["alpine", "busybox", "debian"]));
+        assert_eq!(lock, LockFile { images: vec![locked("alpine", "sha256:dd"), locked("busybox", "sha256:aa"), locked("debian", "sha256:ee")] });
+    }
+
+    #[test]
+    fn test_check() {
+        let lock = LockFile { images: vec![locked("alpine", "sha256:aa"), locked("busybox", "sha256:bb")] };
+        lock.check(&refs(&["alpine", "busybox", "scratch", "debian@sha256:cc"])).unwrap();
+        let err = lock.check(&refs(&["alpine", "debian"])).unwrap_err().to_string();
+        assert_eq!(err, "Pinned images don't match the burt file:\n  missing debian\n  unused busybox");
+    }
+}
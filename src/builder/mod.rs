@@ -1,11 +1,28 @@
 
 mod artifact;
+mod backend;
 mod build;
+mod cache;
+mod clean;
+mod command;
 mod container;
+mod copy;
+mod lock;
+mod mount;
+pub mod interrupt;
+mod prune;
+mod secret;
+mod statcache;
 mod template;
 mod hashfile;
 
-pub use build::{Build, BurtCache};
+pub use backend::{new_backend, Backend, BackendKind, Network, PullPolicy};
+pub use build::{Build, BuildOptions, BurtCache, KeysUnknown, NoCache};
+pub use cache::{export_cache, import_cache};
+pub use clean::clean;
+pub use lock::{ImageRef, LockFile};
+pub use prune::{prune, with_descendants, PruneFilter};
+pub use secret::Secret;
 pub(crate) use build::ContainerSrc;
 
 pub(crate) use container::{
@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use sha2::Digest;
+
+use super::backend::Mount;
+use super::build::BurtCache;
+use super::copy::CopyArchive;
+use super::secret::Secret;
+
+/// Name of the source of a writable bind mount in its temporary directory.
+const BIND_SOURCE: &str = "source";
+
+/// A `--mount` option of a RUN command.
+#[derive(Debug, PartialEq)]
+pub enum MountSpec {
+    /// A directory kept between builds and shared by every RUN mounting the same id.
+    Cache {
+        id: String,
+        target: String,
+    },
+    /// A file or directory of the host, relative to the burt file.
+    ///
+    /// Writes to a `rw` mount go to a copy of the source and are discarded.
+    Bind {
+        source: String,
+        target: String,
+        read_write: bool,
+    },
+    /// A file holding a `--secret` given on the command line, left out of the key of the step.
+    Secret {
+        id: String,
+        target: String,
+    },
+}
+
+/// A mount with its part of the key of the step worked out, set up with [`PreparedMount::attach`]
+/// only once the step actually runs.
+pub struct PreparedMount {
+    source: MountSource,
+    target: PathBuf,
+    read_only: bool,
+    /// What the mount contributes to the key of the step, if anything.
+    pub key: Option<String>,
+}
+
+enum MountSource {
+    Path(PathBuf),
+    /// Unpacked to a temporary directory, for writes to be discarded.
+    Copy(RefCell<CopyArchive>),
+    /// `None` if the secret was not given, which is only an error if the step runs.
+    Secret {
+        id: String,
+        secret: Option<Secret>,
+    },
+    /// The socket of `SSH_AUTH_SOCK`, which is only needed if the step runs.
+    SshAgent,
+}
+
+impl MountSpec {
+    pub fn prepare(&self, dir: &Path, cache: &BurtCache) -> anyhow::Result<PreparedMount> {
+        match self {
+            MountSpec::Cache { id, target } => {
+                let source = cache_dir(id);
+                std::fs::create_dir_all(&source)?;
+                Ok(PreparedMount {
+                    source: MountSource::Path(source),
+                    target: PathBuf::from(target),
+                    read_only: false,
+                    key: None,
+                })
+            }
+            MountSpec::Bind { source, target, read_write } => {
+                let source = dir.join(cache.context_path(dir, Path::new(source))?);
+                let mut archive = CopyArchive::default();
+                archive.add_path(&source, Path::new(BIND_SOURCE))
+                    .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", source.display(), e))?;
+                let hash = cache.with_stat_cache(|stats| archive.key(stats))?;
+
+                Ok(PreparedMount {
+                    source: if *read_write { MountSource::Copy(RefCell::new(archive)) } else { MountSource::Path(source) },
+                    target: PathBuf::from(target),
+                    read_only: !read_write,
+                    key: Some(format!("bind:{hash}:{target}:{}", if *read_write { "rw" } else { "ro" })),
+                })
+            }
+            MountSpec::Secret { id, target } => {
+                Ok(PreparedMount {
+                    source: MountSource::Secret {
+                        id: id.clone(),
+                        secret: cache.secret(id).cloned(),
+                    },
+                    target: PathBuf::from(target),
+                    read_only: true,
+                    key: None,
+                })
+            }
+        }
+    }
+}
+
+impl PreparedMount {
+    /// Set up the source of the mount, returning it along with the temporary directory it is in,
+    /// if any, which has to be kept for as long as the mount is used.
+    pub fn attach(&self) -> anyhow::Result<(Mount, Option<Rc<tempfile::TempDir>>)> {
+        let (source, scratch) = match &self.source {
+            MountSource::Path(path) => (path.clone(), None),
+            MountSource::Copy(archive) => {
+                let tempdir = tempfile::tempdir()?;
+                let mut tarfile = archive.borrow_mut().write_tar(tempfile::tempfile()?)?;
+                tarfile.rewind()?;
+                tar::Archive::new(tarfile).unpack(tempdir.path())?;
+                (tempdir.path().join(BIND_SOURCE), Some(tempdir))
+            }
+            MountSource::Secret { id, secret } => {
+                let secret = secret.as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Secret {} was not given, pass it with --secret id={},src=PATH", id, id))?;
+                let dir = secret.write_temp()?;
+                (dir.path().join("secret"), Some(dir))
+            }
+            MountSource::SshAgent => {
+                let socket = std::env::var_os("SSH_AUTH_SOCK")
+                    .ok_or_else(|| anyhow::anyhow!("RUN --ssh needs an SSH agent, but SSH_AUTH_SOCK is not set"))?;
+                (std::path::absolute(socket)?, None)
+            }
+        };
+
+        Ok((Mount {
+            source,
+            target: self.target.clone(),
+            read_only: self.read_only,
+        }, scratch.map(Rc::new)))
+    }
+}
+
+impl std::str::FromStr for MountSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut kind = None;
+        let mut id = None;
+        let mut source = None;
+        let mut target = None;
+        let mut read_write = false;
+        for field in s.split(',') {
+            match field.split_once('=') {
+                Some(("type", v)) => kind = Some(v),
+                Some(("id", v)) => id = Some(v),
+                Some(("source" | "src", v)) => source = Some(v),
+                Some(("target" | "dst" | "destination", v)) => target = Some(v),
+                None if field == "rw" || field == "readwrite" => read_write = true,
+                None if field == "ro" || field == "readonly" => read_write = false,
+                _ => anyhow::bail!("Unknown mount option {}", field),
+            }
+        }
+
+        if kind == Some("secret") {
+            let id = id.ok_or_else(|| anyhow::anyhow!("Mount {} has no id", s))?;
+            return Ok(MountSpec::Secret {
+                id: id.to_owned(),
+                target: target.map(ToOwned::to_owned).unwrap_or_else(|| format!("/run/secrets/{id}")),
+            });
+        }
+
+        let target = target.ok_or_else(|| anyhow::anyhow!("Mount {} has no target", s))?;
+        match kind {
+            Some("cache") => Ok(MountSpec::Cache {
+                id: id.unwrap_or(target).to_owned(),
+                target: target.to_owned(),
+            }),
+            Some("bind") => Ok(MountSpec::Bind {
+                source: source.ok_or_else(|| anyhow::anyhow!("Mount {} has no source", s))?.to_owned(),
+                target: target.to_owned(),
+                read_write,
+            }),
+            Some(kind) => anyhow::bail!("Unknown mount type {}", kind),
+            None => anyhow::bail!("Mount {} has no type", s),
+        }
+    }
+}
+
+/// Where `RUN --ssh` mounts the socket of the SSH agent in the container.
+pub const SSH_AGENT_SOCK: &str = "/run/burt/ssh-agent.sock";
+
+/// The socket of the SSH agent of the user, mounted for `RUN --ssh`.
+pub fn ssh_agent() -> PreparedMount {
+    PreparedMount {
+        source: MountSource::SshAgent,
+        target: PathBuf::from(SSH_AGENT_SOCK),
+        read_only: false,
+        key: None,
+    }
+}
+
+/// Host directory of the cache mount `id`, named so that it is still recognizable.
+fn cache_dir(id: &str) -> PathBuf {
+    let name: String = id.trim_matches('/').chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    let hash = sha2::Sha256::digest(id.as_bytes());
+    let suffix: String = hash[..4].iter().map(|b| format!("{b:02x}")).collect();
+    crate::state_dir().join("cache-mounts").join(format!("{name}-{suffix}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cache() {
+        assert_eq!("type=cache,target=/root/.cargo/registry".parse::<MountSpec>().unwrap(), MountSpec::Cache {
+            id: "/root/.cargo/registry".to_owned(),
+            target: "/root/.cargo/registry".to_owned(),
+        });
+        assert_eq!("type=cache,id=apt,dst=/var/cache/apt".parse::<MountSpec>().unwrap(), MountSpec::Cache {
+            id: "apt".to_owned(),
+            target: "/var/cache/apt".to_owned(),
+        });
+        assert!("type=cache".parse::<MountSpec>().is_err());
+        assert!("target=/a".parse::<MountSpec>().is_err());
+        assert!("type=tmpfs,target=/a".parse::<MountSpec>().is_err());
+        assert!("type=cache,target=/a,bogus".parse::<MountSpec>().is_err());
+    }
+
+    #[test]
+    fn test_parse_bind() {
+        assert_eq!("type=bind,source=./src,target=/src".parse::<MountSpec>().unwrap(), MountSpec::Bind {
+            source: "./src".to_owned(),
+            target: "/src".to_owned(),
+            read_write: false,
+        });
+        assert_eq!("type=bind,src=src,dst=/src,rw".parse::<MountSpec>().unwrap(), MountSpec::Bind {
+            source: "src".to_owned(),
+            target: "/src".to_owned(),
+            read_write: true,
+        });
+        assert!("type=bind,target=/src".parse::<MountSpec>().is_err());
+    }
+
+    #[test]
+    fn test_parse_secret() {
+        assert_eq!("type=secret,id=npm".parse::<MountSpec>().unwrap(), MountSpec::Secret {
+            id: "npm".to_owned(),
+            target: "/run/secrets/npm".to_owned(),
+        });
+        assert_eq!("type=secret,id=npm,target=/root/.npmrc".parse::<MountSpec>().unwrap(), MountSpec::Secret {
+            id: "npm".to_owned(),
+            target: "/root/.npmrc".to_owned(),
+        });
+        assert!("type=secret,target=/a".parse::<MountSpec>().is_err());
+    }
+
+    #[test]
+    fn test_cache_dir() {
+        assert_ne!(cache_dir("a/b"), cache_dir("a_b"));
+        let name = cache_dir("/root/.cargo/registry").file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("root_.cargo_registry-"), "{name}");
+    }
+}
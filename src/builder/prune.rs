@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use super::backend::Backend;
+
+#[derive(Default)]
+pub struct PruneFilter {
+    /// Remove images created longer ago than this.
+    pub older_than: Option<Duration>,
+    /// Remove the oldest images until the rest fit in this many bytes.
+    pub max_size: Option<u64>,
+    /// Remove images whose key is not in this set.
+    pub reachable: Option<HashSet<String>>,
+    /// Remove every image.
+    pub all: bool,
+}
+
+impl PruneFilter {
+    fn is_empty(&self) -> bool {
+        self.older_than.is_none() && self.max_size.is_none() && self.reachable.is_none() && !self.all
+    }
+}
+
+/// Remove cached images selected by `filter`, which has to select something.
+///
+/// Images are visited from the newest, and an image that a kept image was built on is kept too.
+pub fn prune(backend: &dyn Backend, filter: &PruneFilter, dry_run: bool) -> anyhow::Result<()> {
+    if filter.is_empty() {
+        anyhow::bail!("Nothing to prune, give --older-than, --max-size, --unreachable or --all");
+    }
+
+    let mut images = backend.list_cached_images()?;
+    images.sort_by_key(|i| std::cmp::Reverse(i.created));
+
+    let now = SystemTime::now();
+    let mut kept_size = 0;
+    let mut removed_size = 0;
+    // Keys of the images that kept images are built on, which can't be removed before them.
+    let mut parents = HashSet::new();
+    for image in &images {
+        if parents.contains(&image.key) {
+            kept_size += image.size;
+            parents.extend(image.parent.clone());
+            continue;
+        }
+
+        let too_old = filter.older_than.is_some_and(|age| {
+            now.duration_since(image.created).is_ok_and(|d| d > age)
+        });
+        let unreachable = filter.reachable.as_ref().is_some_and(|keys| !keys.contains(&image.key));
+        let over_size = filter.max_size.is_some_and(|max| kept_size + image.size > max);
+
+        if filter.all || too_old || unreachable || over_size {
+            if dry_run {
+                println!("Would remove {} {} ({} bytes)", image.id, image.key, image.size);
+            } else {
+                println!("Removing {} {} ({} bytes)", image.id, image.key, image.size);
+                backend.remove_image(&image.id)?;
+            }
+            removed_size += image.size;
+        } else {
+            kept_size += image.size;
+            parents.extend(image.parent.clone());
+        }
+    }
+
+    println!("Total reclaimed: {removed_size} bytes");
+
+    Ok(())
+}
+
+/// `keys` with the keys of every cached image built on one of `roots`, however many steps later.
+pub fn with_descendants(backend: &dyn Backend, mut keys: HashSet<String>, roots: &HashSet<String>) -> anyhow::Result<HashSet<String>> {
+    if roots.is_empty() {
+        return Ok(keys);
+    }
+
+    let images = backend.list_cached_images()?;
+    let mut roots = roots.clone();
+    loop {
+        let children: Vec<_> = images.iter()
+            .filter(|i| i.parent.as_ref().is_some_and(|p| roots.contains(p)) && !roots.contains(&i.key))
+            .map(|i| i.key.clone())
+            .collect();
+        if children.is_empty() {
+            return Ok(keys);
+        }
+        roots.extend(children.iter().cloned());
+        keys.extend(children);
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// A value given on the command line with `--secret`, for RUN commands to mount.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Secret {
+    pub id: String,
+    pub source: SecretSource,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SecretSource {
+    File(PathBuf),
+    Env(String),
+}
+
+impl Secret {
+    /// Read the value, which from then on is redacted from the output and errors of commands.
+    pub fn read(&self) -> anyhow::Result<Vec<u8>> {
+        let value = match &self.source {
+            SecretSource::File(path) => std::fs::read(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read secret {} from {}: {}", self.id, path.display(), e))?,
+            SecretSource::Env(name) => std::env::var_os(name)
+                .ok_or_else(|| anyhow::anyhow!("Secret {} is missing, {} is not set", self.id, name))?
+                .into_encoded_bytes(),
+        };
+        super::command::redact(&String::from_utf8_lossy(&value));
+        Ok(value)
+    }
+
+    /// Write the value to a new file only readable by the current user.
+    ///
+    /// The file is put in a tmpfs, so the value is never written to disk.
+    pub fn write_temp(&self) -> anyhow::Result<tempfile::TempDir> {
+        let dir = memory_dir().ok_or_else(|| anyhow::anyhow!(
+            "Secret {} needs a tmpfs to be put in, at $XDG_RUNTIME_DIR or /dev/shm", self.id,
+        ))?;
+        let value = self.read()?;
+        let dir = tempfile::Builder::new().prefix("burt-secret-").tempdir_in(dir)?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o400)
+            .open(dir.path().join("secret"))?
+            .write_all(&value)?;
+        Ok(dir)
+    }
+}
+
+impl std::str::FromStr for Secret {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut id = None;
+        let mut source = None;
+        for field in s.split(',') {
+            match field.split_once('=') {
+                Some(("id", v)) => id = Some(v),
+                Some(("src" | "source", v)) => source = Some(SecretSource::File(PathBuf::from(v))),
+                Some(("env", v)) => source = Some(SecretSource::Env(v.to_owned())),
+                _ => anyhow::bail!("Unknown secret option {}", field),
+            }
+        }
+
+        let id = id.filter(|id| !id.is_empty()).ok_or_else(|| anyhow::anyhow!("Secret {} has no id", s))?;
+        Ok(Secret {
+            id: id.to_owned(),
+            source: source.ok_or_else(|| anyhow::anyhow!("Secret {} has no src or env", s))?,
+        })
+    }
+}
+
+/// A directory whose files are only kept in memory.
+fn memory_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from)
+        .into_iter()
+        .chain([PathBuf::from("/dev/shm")])
+        .find(|dir| is_tmpfs(dir))
+}
+
+fn is_tmpfs(dir: &Path) -> bool {
+    let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_encoded_bytes()) else {
+        return false;
+    };
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    unsafe { stat.assume_init() }.f_type == libc::TMPFS_MAGIC
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("id=npm,src=./token".parse::<Secret>().unwrap(), Secret {
+            id: "npm".to_owned(),
+            source: SecretSource::File(PathBuf::from("./token")),
+        });
+        assert_eq!("env=TOKEN,id=x".parse::<Secret>().unwrap(), Secret {
+            id: "x".to_owned(),
+            source: SecretSource::Env("TOKEN".to_owned()),
+        });
+        assert!("id=x".parse::<Secret>().is_err());
+        assert!("src=./token".parse::<Secret>().is_err());
+        assert!("id=x,env=A,bogus".parse::<Secret>().is_err());
+    }
+}
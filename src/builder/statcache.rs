@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use base64::prelude::*;
+
+/// Content digests of local files, remembered between runs.
+///
+/// An entry is only used while the file's size, mtime and inode are unchanged.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct StatCache {
+    files: HashMap<PathBuf, StatEntry>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StatEntry {
+    stat: FileStat,
+    digest: String,
+}
+
+#[derive(Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileStat {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    dev: u64,
+    inode: u64,
+}
+
+impl FileStat {
+    pub fn new(meta: &fs::Metadata) -> Self {
+        Self {
+            size: meta.size(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            dev: meta.dev(),
+            inode: meta.ino(),
+        }
+    }
+}
+
+impl StatCache {
+    /// Load the cache from `path`, starting empty if it is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        fs::File::open(path).ok()
+            .and_then(|f| serde_json::from_reader(io::BufReader::new(f)).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let parent = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)?;
+        let mut file = tempfile::NamedTempFile::new_in(parent)?;
+        serde_json::to_writer(io::BufWriter::new(&mut file), self)?;
+        file.persist(path)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    pub fn get(&self, path: &Path, stat: &FileStat) -> Option<Vec<u8>> {
+        let entry = self.files.get(path)?;
+        if &entry.stat != stat {
+            return None;
+        }
+        BASE64_STANDARD.decode(&entry.digest).ok()
+    }
+
+    pub fn insert(&mut self, path: PathBuf, stat: FileStat, digest: &[u8]) {
+        self.files.insert(path, StatEntry {
+            stat,
+            digest: BASE64_STANDARD.encode(digest),
+        });
+        self.changed = true;
+    }
+}
@@ -8,7 +8,7 @@ use nom::character::anychar;
 use nom::character::complete::{alpha1, alphanumeric1, char, line_ending, multispace0, not_line_ending};
 use nom::combinator::{all_consuming, cut, eof, opt, recognize, value};
 use nom::error::context;
-use nom::multi::{many0_count, many1, many1_count, separated_list0, separated_list1};
+use nom::multi::{many0, many0_count, many1, many1_count, separated_list0, separated_list1};
 use nom::sequence::{delimited, pair, preceded, terminated};
 use nom::{Finish, IResult, Parser};
 
@@ -159,16 +159,29 @@ where
 }
 
 fn parse_from_command(input: &str) -> ParseResult<FromCommand> {
-    let args = alt((
+    let image = alt((
         arg_targetref.map(FromImage::Target),
         arg_string.map(FromImage::Image)
     ));
-
-    command("FROM image", tag("FROM"), args).map(|src| {
-        FromCommand {
-            src
+    let args = (command_options, image).map_res(|(options, src)| {
+        let mut platform = None;
+        for option in options {
+            match option {
+                ("platform", Some(v)) => platform = Some(v.to_owned()),
+                _ => return Err("unknown option"),
+            }
         }
-    }).parse(input)
+        if platform.is_some() && matches!(src, FromImage::Target(_)) {
+            return Err("--platform only applies to images");
+        }
+
+        Ok(FromCommand {
+            src,
+            platform,
+        })
+    });
+
+    command("FROM [--platform=platform] image", tag("FROM"), args).parse(input)
 }
 
 fn parse_run_command_args(input: &str) -> ParseResult<RunCommandArgs> {
@@ -178,12 +191,43 @@ fn parse_run_command_args(input: &str) -> ParseResult<RunCommandArgs> {
     )).parse(input)
 }
 
+fn option_name(input: &str) -> ParseResult<'_, &str> {
+    recognize(pair(alpha1, many0_count(alt((alphanumeric1, tag("-")))))).parse(input)
+}
+
+fn command_option(input: &str) -> ParseResult<'_, (&str, Option<&str>)> {
+    preceded(tag("--"), (option_name, opt(preceded(tag("="), jinja_nonspace)))).parse(input)
+}
+
+fn command_options(input: &str) -> ParseResult<'_, Vec<(&str, Option<&str>)>> {
+    many0(terminated(command_option, space1)).parse(input)
+}
+
 fn parse_run_command(input: &str) -> ParseResult<RunCommand> {
-    command("RUN args", tag("RUN"), parse_run_command_args).map(|cmd| {
-        RunCommand {
-            cmd
+    let args = (command_options, parse_run_command_args).map_res(|(options, cmd)| {
+        let mut run = RunOptions::default();
+        for option in options {
+            match option {
+                ("no-cache", None) => run.no_cache = true,
+                ("timeout", Some(v)) => run.timeout = Some(v.to_owned()),
+                ("retry", Some(v)) => run.retry = Some(v.to_owned()),
+                ("memory", Some(v)) => run.memory = Some(v.to_owned()),
+                ("cpus", Some(v)) => run.cpus = Some(v.to_owned()),
+                ("network", Some(v)) => run.network = Some(v.to_owned()),
+                ("mount", Some(v)) => run.mounts.push(v.to_owned()),
+                ("secret", Some(v)) => run.secrets.push(v.to_owned()),
+                ("ssh", None) => run.ssh = true,
+                _ => return Err("unknown option"),
+            }
         }
-    }).parse(input)
+
+        Ok(RunCommand {
+            cmd,
+            options: run,
+        })
+    });
+
+    command("RUN [--option...] args", tag("RUN"), args).parse(input)
 }
 
 fn parse_workdir_command(input: &str) -> ParseResult<WorkDirCommand> {
@@ -225,7 +269,8 @@ fn parse_copy_command(input: &str) -> ParseResult<CopyCommand> {
         let dest = s.pop().unwrap();
         Ok(CopyCommand {
             src: s.into_iter().map(CopySource::LocalPath).collect(),
-            dest
+            dest,
+            options: CopyOptions::default(),
         })
     });
 
@@ -243,14 +288,23 @@ fn parse_copy_command(input: &str) -> ParseResult<CopyCommand> {
                     CopySource::LocalPath(p)
                 }
             }).collect(),
-            dest
+            dest,
+            options: CopyOptions::default(),
         })
     });
 
-    let args = alt((
-        copy_string_list,
-        copy_args
-    ));
+    let args = (command_options, alt((copy_string_list, copy_args))).map_res(|(options, mut copy)| {
+        for option in options {
+            match option {
+                ("chown", Some(v)) => copy.options.chown = Some(v.to_owned()),
+                ("chmod", Some(v)) => copy.options.chmod = Some(v.to_owned()),
+                ("parents", None) => copy.options.parents = true,
+                ("if-exists", None) => copy.options.if_exists = true,
+                _ => return Err("unknown option"),
+            }
+        }
+        Ok(copy)
+    });
 
     command("COPY src... dest", tag("COPY"), args).parse(input)
 }
@@ -267,6 +321,10 @@ fn parse_save_artifact_command(input: &str) -> ParseResult<SaveArtifactCommand>
     }).parse(input)
 }
 
+fn into_var(input: &str) -> ParseResult<'_, &str> {
+    preceded((space1, tag("INTO"), space1), var_name).parse(input)
+}
+
 fn parse_read_file_command(input: &str) -> ParseResult<ReadFileCommand> {
     command("READ FILE src INTO dest", tag("READ FILE"), (jinja_nonspace, var_name)).map(|r| {
         ReadFileCommand {
@@ -277,12 +335,36 @@ fn parse_read_file_command(input: &str) -> ParseResult<ReadFileCommand> {
 }
 
 fn parse_read_run_command(input: &str) -> ParseResult<ReadRunCommand> {
-    command("READ RUN command INTO dest", tag("READ RUN"), (parse_run_command_args, var_name)).map(|r| {
-        ReadRunCommand {
-            src: r.0,
-            dest: r.1.to_owned(),
+    // The command itself may contain " INTO ", so the last one on the line ends it.
+    let command_string = command_string.map_res(|line: &str| {
+        let (cmd, dest) = line.trim_end().rsplit_once(" INTO ").ok_or("expected INTO dest")?;
+        let cmd = cmd.trim_end();
+        match all_consuming(var_name).parse(dest.trim_start()) {
+            Ok((_, dest)) if !cmd.is_empty() => Ok((RunCommandArgs::String(cmd.to_owned()), dest)),
+            _ => Err("expected INTO dest"),
         }
-    }).parse(input)
+    });
+    let src = alt((
+        (string_list.map(RunCommandArgs::List), into_var),
+        command_string,
+    ));
+    let args = (command_options, src).map_res(|(options, (src, dest))| {
+        let mut network = None;
+        for option in options {
+            match option {
+                ("network", Some(v)) => network = Some(v.to_owned()),
+                _ => return Err("unknown option"),
+            }
+        }
+
+        Ok(ReadRunCommand {
+            src,
+            dest: dest.to_owned(),
+            network,
+        })
+    });
+
+    command("READ RUN [--option...] command INTO dest", tag("READ RUN"), args).parse(input)
 }
 
 fn parse_target_command(input: &str) -> ParseResult<Command> {
@@ -376,8 +458,85 @@ mod tests {
 
     #[test]
     fn test_run_command() {
-        assert_eq!(parse_run_command("RUN hello"), Ok(("", RunCommand { cmd: RunCommandArgs::String("hello".to_owned())})));
-        assert_eq!(parse_run_command("RUN hello\nnext"), Ok(("next", RunCommand { cmd: RunCommandArgs::String("hello".to_owned())})));
+        assert_eq!(parse_run_command("RUN hello"), Ok(("", RunCommand { cmd: RunCommandArgs::String("hello".to_owned()), options: RunOptions::default() })));
+        assert_eq!(parse_run_command("RUN hello\nnext"), Ok(("next", RunCommand { cmd: RunCommandArgs::String("hello".to_owned()), options: RunOptions::default() })));
+    }
+
+    #[test]
+    fn test_run_command_options() {
+        let no_cache = RunOptions { no_cache: true, ..RunOptions::default() };
+        assert_eq!(parse_run_command("RUN --no-cache apk update"), Ok(("", RunCommand { cmd: RunCommandArgs::String("apk update".to_owned()), options: no_cache })));
+        let no_cache = RunOptions { no_cache: true, ..RunOptions::default() };
+        assert_eq!(parse_run_command("RUN --no-cache [\"true\"]"), Ok(("", RunCommand { cmd: RunCommandArgs::List(vec!["true".to_owned()]), options: no_cache })));
+        assert!(parse_run_command("RUN --bogus true").is_err());
+        assert!(parse_run_command("RUN --timeout true").is_err());
+
+        let limits = RunOptions {
+            timeout: Some("10m".to_owned()),
+            retry: Some("3".to_owned()),
+            memory: Some("2g".to_owned()),
+            cpus: Some("{{ cpus }}".to_owned()),
+            ..RunOptions::default()
+        };
+        assert_eq!(parse_run_command("RUN --timeout=10m --retry=3 --memory=2g --cpus={{ cpus }} make"), Ok(("", RunCommand { cmd: RunCommandArgs::String("make".to_owned()), options: limits })));
+
+        let mounts = RunOptions {
+            mounts: vec!["type=cache,target=/a".to_owned(), "type=cache,target=/b,id=b".to_owned()],
+            ..RunOptions::default()
+        };
+        assert_eq!(parse_run_command("RUN --mount=type=cache,target=/a --mount=type=cache,target=/b,id=b make"), Ok(("", RunCommand { cmd: RunCommandArgs::String("make".to_owned()), options: mounts })));
+
+        let secrets = RunOptions {
+            secrets: vec!["id=npm,target=/root/.npmrc".to_owned()],
+            ..RunOptions::default()
+        };
+        assert_eq!(parse_run_command("RUN --secret=id=npm,target=/root/.npmrc npm ci"), Ok(("", RunCommand { cmd: RunCommandArgs::String("npm ci".to_owned()), options: secrets })));
+
+        let ssh = RunOptions { ssh: true, ..RunOptions::default() };
+        assert_eq!(parse_run_command("RUN --ssh git clone"), Ok(("", RunCommand { cmd: RunCommandArgs::String("git clone".to_owned()), options: ssh })));
+    }
+
+    #[test]
+    fn test_read_run_command() {
+        assert_eq!(parse_read_run_command("READ RUN uname -m INTO arch"), Ok(("", ReadRunCommand { src: RunCommandArgs::String("uname -m".to_owned()), dest: "arch".to_owned(), network: None })));
+        assert_eq!(parse_read_run_command("READ RUN [\"uname\"] INTO arch\nnext"), Ok(("next", ReadRunCommand { src: RunCommandArgs::List(vec!["uname".to_owned()]), dest: "arch".to_owned(), network: None })));
+        assert_eq!(parse_read_run_command("READ RUN --network=none uname INTO arch"), Ok(("", ReadRunCommand { src: RunCommandArgs::String("uname".to_owned()), dest: "arch".to_owned(), network: Some("none".to_owned()) })));
+        assert_eq!(parse_read_run_command("READ RUN psql -c \"INSERT INTO t VALUES (1)\" INTO out"), Ok(("", ReadRunCommand { src: RunCommandArgs::String("psql -c \"INSERT INTO t VALUES (1)\"".to_owned()), dest: "out".to_owned(), network: None })));
+        assert_eq!(parse_read_run_command("READ RUN echo a \\\n    b INTO out"), Ok(("", ReadRunCommand { src: RunCommandArgs::String("echo a \\\n    b".to_owned()), dest: "out".to_owned(), network: None })));
+        assert!(parse_read_run_command("READ RUN uname\nINTO arch").is_err());
+        assert!(parse_read_run_command("READ RUN --retry=2 uname INTO arch").is_err());
+    }
+
+    #[test]
+    fn test_from_command() {
+        let (_, from) = parse_from_command("FROM --platform=linux/arm64 alpine@sha256:abc").unwrap();
+        assert!(matches!(from.src, FromImage::Image(ref i) if i == "alpine@sha256:abc"));
+        assert_eq!(from.platform.as_deref(), Some("linux/arm64"));
+
+        let (_, from) = parse_from_command("FROM scratch").unwrap();
+        assert!(matches!(from.src, FromImage::Image(ref i) if i == "scratch"));
+        assert_eq!(from.platform, None);
+
+        assert!(parse_from_command("FROM --platform=linux/arm64 +base").is_err());
+        assert!(parse_from_command("FROM --pull alpine").is_err());
+    }
+
+    #[test]
+    fn test_copy_command_options() {
+        let (rest, copy) = parse_copy_command("COPY --chown=app:app --chmod=0644 --parents --if-exists a/b.txt c.txt /dest/").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(copy.options, CopyOptions {
+            chown: Some("app:app".to_owned()),
+            chmod: Some("0644".to_owned()),
+            parents: true,
+            if_exists: true,
+        });
+        assert_eq!(copy.src.len(), 2);
+        assert_eq!(copy.dest, "/dest/");
+
+        let (_, copy) = parse_copy_command("COPY a.txt /").unwrap();
+        assert_eq!(copy.options, CopyOptions::default());
+        assert!(parse_copy_command("COPY --bogus a.txt /").is_err());
     }
 
     #[test]
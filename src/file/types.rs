@@ -26,6 +26,8 @@ pub enum Command {
 #[derive(Debug)]
 pub struct FromCommand {
     pub src: FromImage,
+    /// Platform of the image, such as `linux/arm64`, still to be rendered.
+    pub platform: Option<String>,
 }
 
 #[derive(Debug)]
@@ -52,6 +54,22 @@ pub struct SetCommand {
 #[derive(Debug, Eq, PartialEq)]
 pub struct RunCommand {
     pub cmd: RunCommandArgs,
+    pub options: RunOptions,
+}
+
+/// The `--option` flags of a RUN command, with values still to be rendered.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct RunOptions {
+    pub no_cache: bool,
+    pub timeout: Option<String>,
+    pub retry: Option<String>,
+    pub memory: Option<String>,
+    pub cpus: Option<String>,
+    pub network: Option<String>,
+    pub mounts: Vec<String>,
+    pub secrets: Vec<String>,
+    /// Forward the SSH agent of the user.
+    pub ssh: bool,
 }
 
 #[derive(Debug)]
@@ -81,6 +99,20 @@ pub enum CopySource {
 pub struct CopyCommand {
     pub src: Vec<CopySource>,
     pub dest: String,
+    pub options: CopyOptions,
+}
+
+/// The `--option` flags of a COPY command, with values still to be rendered.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct CopyOptions {
+    /// `user[:group]`, by name or id.
+    pub chown: Option<String>,
+    /// Octal mode of every copied file and directory.
+    pub chmod: Option<String>,
+    /// Keep the path of each source below the destination.
+    pub parents: bool,
+    /// Skip sources that don't exist.
+    pub if_exists: bool,
 }
 
 #[derive(Debug)]
@@ -89,8 +121,9 @@ pub struct ReadFileCommand {
     pub dest: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub struct ReadRunCommand {
     pub src: RunCommandArgs,
     pub dest: String,
+    pub network: Option<String>,
 }
@@ -1,14 +1,16 @@
 
+use std::collections::{BTreeSet, HashSet};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::LazyLock;
 
 use anyhow::Context;
-use builder::BurtCache;
+use builder::{Backend, BuildOptions, BurtCache, LockFile, NoCache, PullPolicy};
 
 mod args;
 mod builder;
 mod file;
+mod units;
 
 fn current_exe() -> &'static Path {
     static CE: LazyLock<PathBuf> = LazyLock::new(|| {
@@ -21,6 +23,22 @@ fn current_exe() -> &'static Path {
     &CE
 }
 
+/// Directory for state kept between runs, such as the COPY stat cache.
+fn state_dir() -> &'static Path {
+    static SD: LazyLock<PathBuf> = LazyLock::new(|| {
+        if let Some(p) = std::env::var_os("BURT_STATE_DIR") {
+            PathBuf::from(p)
+        } else if let Some(p) = std::env::var_os("XDG_STATE_HOME") {
+            PathBuf::from(p).join("burt")
+        } else if let Some(p) = std::env::var_os("HOME") {
+            PathBuf::from(p).join(".local/state/burt")
+        } else {
+            std::env::temp_dir().join("burt")
+        }
+    });
+    &SD
+}
+
 fn read_burt_file(path: &Path) -> anyhow::Result<file::RootSection> {
     let file = std::fs::File::open(path)
         .with_context(|| format!("Failed to open {}", path.display()))?;
@@ -29,24 +47,64 @@ fn read_burt_file(path: &Path) -> anyhow::Result<file::RootSection> {
     Ok(result)
 }
 
-fn build_targets(path: &Path, targets: Vec<String>, export_artifacts: bool, defines: Vec<String>) -> anyhow::Result<()> {
-    let burt_cache = Rc::new(BurtCache::default());
+fn build_options(global: &args::GlobalArgs) -> anyhow::Result<BuildOptions> {
+    let no_cache = match global.no_cache.as_deref() {
+        None => NoCache::None,
+        Some(targets) if targets.iter().any(|t| t.is_empty()) => NoCache::All,
+        Some(targets) => NoCache::Targets(targets.iter().map(|t| {
+            t.strip_prefix('+').map(ToOwned::to_owned).ok_or_else(|| anyhow::anyhow!("Unknown target {}", t))
+        }).collect::<Result<_, _>>()?),
+    };
+
+    Ok(BuildOptions {
+        no_cache,
+        keys_only: false,
+        interactive_on_failure: global.interactive_on_failure,
+        network_default: global.network_default,
+        allow_outside_context: global.allow_outside_context,
+        secrets: global.secret.clone(),
+        pull: if global.offline { PullPolicy::Never } else { global.pull },
+        lock: LockFile::load(&global.file)?,
+        locked: global.locked,
+    })
+}
+
+/// Options for building targets, which with --locked first checks that the lock file matches the burt file.
+fn checked_build_options(backend: &Rc<dyn Backend>, global: &args::GlobalArgs) -> anyhow::Result<BuildOptions> {
+    let options = build_options(global)?;
+    if global.locked {
+        let path = LockFile::path(&global.file);
+        let lock = options.lock.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("{} has no pins for {}, run burt lock", path.display(), global.file.display()))?;
+        lock.check(&images(backend, global, &[])?)
+            .with_context(|| format!("{} is stale, run burt lock", path.display()))?;
+    }
+    Ok(options)
+}
+
+fn new_build(burt_cache: &Rc<BurtCache>, global: &args::GlobalArgs) -> builder::Build {
+    let mut build = builder::Build::new(burt_cache.clone());
+
+    for define in &global.define {
+        if let Some((k, v)) = define.split_once('=') {
+            build.set(k, v);
+        } else {
+            build.set(define, "");
+        }
+    }
+
+    build
+}
+
+fn build_targets(backend: &Rc<dyn Backend>, global: &args::GlobalArgs, targets: Vec<String>) -> anyhow::Result<()> {
+    let burt_cache = Rc::new(BurtCache::new(backend.clone(), checked_build_options(backend, global)?));
 
     for target in targets {
         if let Some(target) = target.strip_prefix('+') {
-            let mut build = builder::Build::new(burt_cache.clone());
-
-            for define in &defines {
-                if let Some((k, v)) = define.split_once('=') {
-                    build.set(k, v);
-                } else {
-                    build.set(define, "");
-                }
-            }
+            let mut build = new_build(&burt_cache, global);
+            build.build(&global.file, target)?;
 
-            build.build(path, target)?;
-
-            if export_artifacts {
+            if global.artifact {
                 build.export_artifact(".")?;
             }
         } else {
@@ -57,22 +115,140 @@ fn build_targets(path: &Path, targets: Vec<String>, export_artifacts: bool, defi
     Ok(())
 }
 
+fn shell(backend: &Rc<dyn Backend>, global: &args::GlobalArgs, target: &str) -> anyhow::Result<()> {
+    let Some(target) = target.strip_prefix('+') else {
+        anyhow::bail!("Unknown target {}", target);
+    };
+
+    let burt_cache = Rc::new(BurtCache::new(backend.clone(), checked_build_options(backend, global)?));
+    let mut build = new_build(&burt_cache, global);
+    build.build(&global.file, target)?;
+    build.shell()
+}
+
+/// Targets named on the command line, or every target in the burt file if there are none.
+fn targets_or_all(global: &args::GlobalArgs, targets: &[String]) -> anyhow::Result<Vec<String>> {
+    if targets.is_empty() {
+        Ok(read_burt_file(&global.file)?.targets.into_keys().collect())
+    } else {
+        targets.iter().map(|t| {
+            t.strip_prefix('+').map(ToOwned::to_owned).ok_or_else(|| anyhow::anyhow!("Unknown target {}", t))
+        }).collect()
+    }
+}
+
+/// The images of the FROM commands of `targets` and the targets they depend on, by the names written in the burt file.
+fn images(backend: &Rc<dyn Backend>, global: &args::GlobalArgs, targets: &[String]) -> anyhow::Result<BTreeSet<builder::ImageRef>> {
+    let burt_cache = Rc::new(BurtCache::new(backend.clone(), BuildOptions {
+        lock: None,
+        locked: false,
+        ..build_options(global)?
+    }));
+
+    let mut images = BTreeSet::new();
+    for target in targets_or_all(global, targets)? {
+        new_build(&burt_cache, global).images(&global.file, &target, &mut images)?;
+    }
+    Ok(images)
+}
+
+/// Pin the images the targets are built from in the lock file and print the pins.
+///
+/// Pins of other targets are kept, those of images no target uses any more are dropped. With
+/// --locked, this only fails if the lock file would change.
+fn lock(backend: &Rc<dyn Backend>, global: &args::GlobalArgs, targets: &[String]) -> anyhow::Result<()> {
+    let options = build_options(global)?;
+    let used = images(backend, global, &[])?;
+    let wanted = if targets.is_empty() { used.clone() } else { images(backend, global, targets)? };
+
+    let mut lock = LockFile::resolve(backend.as_ref(), &wanted, options.pull)?;
+    if let Some(previous) = &options.lock {
+        lock.keep(previous, &used);
+    }
+    print!("{lock}");
+
+    let path = LockFile::path(&global.file);
+    if global.locked {
+        if options.lock.as_ref() != Some(&lock) {
+            anyhow::bail!("{} is stale, run burt lock without --locked", path.display());
+        }
+        return Ok(());
+    }
+    lock.save(&global.file)
+}
+
+/// Collect the cache keys of every step of `targets`, without running any of them.
+///
+/// The keys after a step that reads from the build can't be worked out, so every image built
+/// on that step is counted as reachable. Images built on a FROM image that is not available
+/// locally are not, as building the target would first pull it again.
+fn reachable_keys(backend: &Rc<dyn Backend>, global: &args::GlobalArgs, targets: &[String]) -> anyhow::Result<HashSet<String>> {
+    let burt_cache = Rc::new(BurtCache::new(backend.clone(), BuildOptions {
+        keys_only: true,
+        no_cache: NoCache::None,
+        ..build_options(global)?
+    }));
+
+    for target in targets_or_all(global, targets)? {
+        let mut build = new_build(&burt_cache, global);
+        match build.build(&global.file, &target) {
+            Err(e) if !e.is::<builder::KeysUnknown>() => return Err(e),
+            _ => (),
+        }
+    }
+
+    builder::with_descendants(backend.as_ref(), burt_cache.seen_keys(), &burt_cache.open_keys())
+}
+
+fn prune(backend: &Rc<dyn Backend>, global: &args::GlobalArgs, prune_args: &args::PruneArgs) -> anyhow::Result<()> {
+    let filter = builder::PruneFilter {
+        older_than: prune_args.older_than.as_deref().map(units::parse_duration).transpose()?,
+        max_size: prune_args.max_size.as_deref().map(units::parse_size).transpose()?,
+        reachable: prune_args.unreachable.then(|| reachable_keys(backend, global, &prune_args.targets)).transpose()?,
+        all: prune_args.all,
+    };
+    builder::prune(backend.as_ref(), &filter, prune_args.dry_run)
+}
+
 fn main() -> anyhow::Result<()> {
     use args::Command;
 
     let args = args::Args::parse();
+    let backend = builder::new_backend(args.global.backend)?;
 
-    match args.command {
-        Command::Build(build_args) => build_targets(&args.global.file, build_args.targets, args.global.artifact, args.global.define),
+    let result = match args.command {
+        Command::Build(build_args) => {
+            builder::interrupt::install()?;
+            build_targets(&backend, &args.global, build_args.targets)
+        },
+        Command::Lock(lock_args) => lock(&backend, &args.global, &lock_args.targets),
+        Command::Prune(prune_args) => prune(&backend, &args.global, &prune_args),
+        Command::Shell(shell_args) => {
+            builder::interrupt::install()?;
+            shell(&backend, &args.global, &shell_args.target)
+        },
+        Command::Clean(clean_args) => builder::clean(backend.as_ref(), clean_args.all, clean_args.dry_run),
+        Command::Cache(args::CacheCommand::Export { file, targets }) => {
+            let keys = (!targets.is_empty()).then(|| reachable_keys(&backend, &args.global, &targets)).transpose()?;
+            builder::export_cache(backend.as_ref(), &file, keys.as_ref())
+        },
+        Command::Cache(args::CacheCommand::Import { file }) => builder::import_cache(backend.as_ref(), &file),
         Command::InternalContainerCopy { src, dest } => {
             builder::perform_container_copy(&src, &dest)
         },
-        Command::InternalExport { path } => {
-            builder::perform_container_export(&path)
+        Command::InternalExport { path, if_exists } => {
+            builder::perform_container_export(&path, if_exists)
         },
         Command::InternalImportTar { path } => {
             builder::perform_container_import_tar(std::io::stdin().lock(), &path)
         }
+    };
+
+    if let Some(signal) = builder::interrupt::interrupted() {
+        eprintln!("Interrupted");
+        std::process::exit(128 + signal);
     }
+
+    result
 }
 
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+/// Parse a duration such as `90s`, `10m`, `2h` or `7d`. A bare number is in seconds.
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let (value, unit) = split_unit(s);
+    let value: u64 = value.parse().map_err(|_| anyhow::anyhow!("Invalid duration {s:?}"))?;
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!("Invalid duration {s:?}"),
+    };
+    Ok(Duration::from_secs(value * scale))
+}
+
+/// Parse a size such as `512k`, `2g` or `1.5 GB` into bytes. Units are powers of 1024.
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    parse_size_in(s, 1024)
+}
+
+/// Parse a size as container engines print it, such as `12.3 MB`. Units are powers of 1000.
+pub fn parse_decimal_size(s: &str) -> anyhow::Result<u64> {
+    parse_size_in(s, 1000)
+}
+
+fn parse_size_in(s: &str, base: u64) -> anyhow::Result<u64> {
+    let (value, unit) = split_unit(s);
+    let value: f64 = value.parse().map_err(|_| anyhow::anyhow!("Invalid size {s:?}"))?;
+    let unit = unit.to_ascii_lowercase();
+    let unit = unit.strip_suffix("ib").or_else(|| unit.strip_suffix('b')).unwrap_or(&unit);
+    let power = match unit {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        _ => anyhow::bail!("Invalid size {s:?}"),
+    };
+    Ok((value * base.pow(power) as f64).round() as u64)
+}
+
+fn split_unit(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    let idx = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    (&s[..idx], s[idx..].trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(7 * 86400));
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("2g").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1.5 GB").unwrap(), 3 << 29);
+        assert_eq!(parse_size("512KiB").unwrap(), 512 << 10);
+        assert!(parse_size("12 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_parse_decimal_size() {
+        assert_eq!(parse_decimal_size("12.3 MB").unwrap(), 12_300_000);
+        assert_eq!(parse_decimal_size("5 kB").unwrap(), 5000);
+        assert_eq!(parse_decimal_size("841B").unwrap(), 841);
+    }
+}
@@ -1,10 +1,9 @@
-use assert_cmd::assert::OutputAssertExt;
 use assert_cmd::cargo::CommandCargoExt;
 use predicates::prelude::*;
 use rstest::rstest;
 use serde::Deserialize;
 use tempfile::TempDir;
-use std::{collections::HashMap, io::Read, path::{Path, PathBuf}, process::Command};
+use std::{collections::HashMap, io::Read, path::{Path, PathBuf}, process::{Command, Stdio}, time::Duration};
 
 #[derive(Debug, Deserialize)]
 struct TestData {
@@ -16,8 +15,15 @@ struct TestData {
 }
 
 #[derive(Default, Debug, Deserialize)]
+#[serde(default)]
 struct TestSetup {
     files: Vec<PathBuf>,
+    /// Unix sockets to listen on, standing in for an SSH agent.
+    sockets: Vec<PathBuf>,
+    /// Skip the test unless it runs as root, which the mock backend needs to give files away.
+    root: bool,
+    /// Backends the test can run on, every one if empty.
+    backends: Vec<String>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -26,7 +32,16 @@ struct TestRun {
     args: Vec<String>,
     status_code: i32,
     stderr_contains: Vec<String>,
+    stdout_contains: Vec<String>,
+    stdout_excludes: Vec<String>,
+    env: HashMap<String, String>,
     verify_files: HashMap<PathBuf, PathBuf>,
+    /// Input for burt, like the commands of a shell it opens.
+    stdin: Option<String>,
+    /// Kill burt with SIGKILL after this many seconds, leaving behind what it was doing.
+    kill_after: Option<f64>,
+    /// Keep the last word of stdout as this name, which `${name}` in the args of later runs stands for.
+    stdout_into: Option<String>,
 }
 
 fn show_file(path: &Path) {
@@ -42,6 +57,11 @@ fn show_file(path: &Path) {
     eprintln!("{}: {:?}", path.display(), buffer);
 }
 
+/// Backend to run the tests with, the hermetic mock unless `BURT_TEST_BACKEND` says otherwise.
+fn test_backend() -> String {
+    std::env::var("BURT_TEST_BACKEND").unwrap_or_else(|_| "mock".to_owned())
+}
+
 fn list_files(path: &Path) -> impl Iterator<Item=PathBuf> {
     let w = walkdir::WalkDir::new(path);
     w.into_iter().filter_map(|s| {
@@ -63,8 +83,18 @@ fn main(
     let mut toml_data = String::new();
     f.read_to_string(&mut toml_data).unwrap();
     let test: TestData = toml::from_str(&toml_data).unwrap();
+    if test.setup.root && test_backend() == "mock" && unsafe { libc::geteuid() } != 0 {
+        eprintln!("Skipped, as it needs root");
+        return;
+    }
+
+    if !test.setup.backends.is_empty() && !test.setup.backends.contains(&test_backend()) {
+        eprintln!("Skipped, as it doesn't run on {}", test_backend());
+        return;
+    }
 
     let temp_dir = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
     
     if burt_name.exists() {
         let burt_filename = temp_dir.path().join("build.burt");
@@ -72,19 +102,46 @@ fn main(
     }
 
     for add_file in &test.setup.files {
-        std::fs::copy(parent.join(add_file), temp_dir.path().join(add_file)).unwrap();
+        let dest = temp_dir.path().join(add_file);
+        std::fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        std::fs::copy(parent.join(add_file), dest).unwrap();
     }
 
+    let _sockets: Vec<_> = test.setup.sockets.iter()
+        .map(|socket| std::os::unix::net::UnixListener::bind(temp_dir.path().join(socket)).unwrap())
+        .collect();
+
     if test.run.is_empty() {
         panic!("No test runs defined");
     }
 
+    let mut values: HashMap<String, String> = HashMap::new();
     for (idx, run) in test.run.iter().enumerate() {
         eprintln!("--- {idx}");
 
         let mut command = Command::cargo_bin("burt").unwrap();
-        command.args(&run.args);
+        command.arg("--backend").arg(test_backend());
+        command.args(run.args.iter().map(|arg| {
+            values.iter().fold(arg.clone(), |arg, (name, value)| arg.replace(&format!("${{{name}}}"), value))
+        }));
+        command.env("BURT_STATE_DIR", state_dir.path());
+        command.env_remove("SSH_AUTH_SOCK");
+        command.envs(&run.env);
         command.current_dir(temp_dir.path());
+
+        if let Some(seconds) = run.kill_after {
+            // What burt started keeps its output open, so it isn't captured.
+            let mut child = command.stdout(Stdio::null()).stderr(Stdio::null()).spawn().unwrap();
+            std::thread::sleep(Duration::from_secs_f64(seconds));
+            child.kill().unwrap();
+            child.wait().unwrap();
+            continue;
+        }
+
+        let mut command = assert_cmd::Command::from_std(command);
+        if let Some(stdin) = &run.stdin {
+            command.write_stdin(stdin.as_bytes());
+        }
         let mut cmd_assert = command.assert();
         
         let files: Vec<_> = list_files(temp_dir.path()).map(|p| p.display().to_string()).collect();
@@ -97,8 +154,20 @@ fn main(
         for s in &run.stderr_contains {
             cmd_assert = cmd_assert.stderr(predicate::str::contains(s));
         }
+        for s in &run.stdout_contains {
+            cmd_assert = cmd_assert.stdout(predicate::str::contains(s));
+        }
+        for s in &run.stdout_excludes {
+            cmd_assert = cmd_assert.stdout(predicate::str::contains(s).not());
+        }
 
         check_files(&path, temp_dir.path(), run, &test);
+
+        if let Some(name) = &run.stdout_into {
+            let stdout = String::from_utf8_lossy(&cmd_assert.get_output().stdout).into_owned();
+            let word = stdout.split_whitespace().last().unwrap_or_else(|| panic!("No output for {name}"));
+            values.insert(name.clone(), word.to_owned());
+        }
     }
 }
 